@@ -0,0 +1,77 @@
+//! how long each stage of naming a video takes: spotting a blue frame, scanning a video for its
+//! title card, and reading one. the detection benches run on a synthetic frame; the others need
+//! real files, so point BENCH_VIDEO at a short video and BENCH_IMAGE at a saved title card to
+//! run them
+
+use std::path::Path;
+use bluey::{blue_share, extract_frames, get_episode_name, get_episode_names, is_color_dominant, Color, ColorThresholds, CsvColumns, Distance, EpisodeIndex, ExtractParams, LineFilter, MatchParams, ModelPaths, Ocr, Preprocess, Scaler};
+use criterion::{black_box, criterion_group, criterion_main, Criterion};
+use image::{Rgb, RgbImage};
+
+// a 1080p frame the colour of Bluey's title card
+fn title_card() -> RgbImage {
+    RgbImage::from_pixel(1920, 1080, Rgb([120, 200, 240]))
+}
+
+fn detection(c: &mut Criterion) {
+    let frame = title_card();
+    let thresholds = ColorThresholds::default();
+    c.bench_function("blue_share", |b| b.iter(|| blue_share(black_box(&frame), &thresholds)));
+    c.bench_function("is_color_dominant", |b| b.iter(|| is_color_dominant(black_box(&frame), Color([120, 200, 240]), 40, 0.8)));
+}
+
+fn extraction(c: &mut Criterion) {
+    let Some(video) = std::env::var_os("BENCH_VIDEO") else {
+        return;
+    };
+    let params = ExtractParams {
+        skip_frames: 0,
+        sample_every: 24,
+        candidates: 1,
+        max_samples: None,
+        scan_start: None,
+        scan_end: None,
+        fallback_best: false,
+        from_end: false,
+        scan_jobs: 1,
+        retry_relaxed: false,
+        pixel_stride: 1,
+        color_sample_region: None,
+        scaler: Scaler::Bilinear,
+        decode_threads: None,
+        thresholds: ColorThresholds::default(),
+    };
+    let mut group = c.benchmark_group("extraction");
+    group.sample_size(10);
+    group.bench_function("extract_frames", |b| b.iter(|| extract_frames(Path::new(&video), &params).unwrap()));
+    let parallel = ExtractParams { scan_jobs: 4, ..params };
+    group.bench_function("extract_frames with 4 scan jobs", |b| b.iter(|| extract_frames(Path::new(&video), &parallel).unwrap()));
+    let point = ExtractParams { scaler: Scaler::Point, ..params };
+    group.bench_function("extract_frames with the point scaler", |b| b.iter(|| extract_frames(Path::new(&video), &point).unwrap()));
+    group.finish();
+}
+
+fn recognition(c: &mut Criterion) {
+    let Some(image) = std::env::var_os("BENCH_IMAGE") else {
+        return;
+    };
+    let frame = image::open(&image).unwrap().into_rgb8();
+    let episodes = get_episode_names("bluey.csv", &CsvColumns::default(), &[]).unwrap();
+    let matching = MatchParams { distance: Distance::Levenshtein, max_distance: None, ambiguity_margin: None, phonetic_tiebreak: false, overrides: None, ignore_words: vec![] };
+    let index = EpisodeIndex::new(&episodes, &matching);
+    let ocr = Ocr::load(&ModelPaths::default(), LineFilter::default(), Preprocess::default(), None).unwrap();
+    let mut group = c.benchmark_group("recognition");
+    group.sample_size(10);
+    group.bench_function("get_episode_name", |b| b.iter(|| get_episode_name(&ocr, &frame, &index, &matching, false).unwrap()));
+    // what reading a frame cost on top of that when the models were loaded for every one
+    group.bench_function("get_episode_name loading the models", |b| b.iter(|| {
+        let ocr = Ocr::load(&ModelPaths::default(), LineFilter::default(), Preprocess::default(), None).unwrap();
+        get_episode_name(&ocr, &frame, &index, &matching, false).unwrap()
+    }));
+    let shrunk = Ocr::load(&ModelPaths::default(), LineFilter::default(), Preprocess { ocr_max_dim: Some(1280), roi: None }, None).unwrap();
+    group.bench_function("get_episode_name shrunk to 1280px", |b| b.iter(|| get_episode_name(&shrunk, &frame, &index, &matching, false).unwrap()));
+    group.finish();
+}
+
+criterion_group!(benches, detection, extraction, recognition);
+criterion_main!(benches);
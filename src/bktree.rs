@@ -0,0 +1,155 @@
+use crate::episodes::{by_distance, match_key};
+use crate::{Distance, Episode, MatchParams};
+
+// below this many episodes comparing against every one is quick enough that a tree isn't worth
+// building
+const MIN_EPISODES: usize = 500;
+
+/// whether looking up the closest episode in a BK-tree is worth building one over comparing it
+/// with every episode: there have to be enough episodes, the distance has to be an edit distance
+/// so the tree's triangle inequality holds, and --phonetic-tiebreak can't need the distance to
+/// every episode
+pub(crate) fn worth_building(episodes: &[Episode], matching: &MatchParams) -> bool {
+    episodes.len() >= MIN_EPISODES
+        && matches!(matching.distance, Distance::Levenshtein | Distance::Damerau)
+        && !matching.phonetic_tiebreak
+}
+
+// every name of every episode, keyed the way they're compared, in a tree where each child is
+// labelled with its distance from its parent. a search can skip every child whose label is
+// further from the parent's distance to the text than the closest found so far
+pub(crate) struct BkTree {
+    damerau: bool,
+    ignore_words: Vec<String>,
+    nodes: Vec<Node>,
+}
+
+struct Node {
+    key: String,
+    // indexes of the episodes with a name that has this key
+    episodes: Vec<usize>,
+    // (distance from this node, index of the child)
+    children: Vec<(usize, usize)>,
+}
+
+impl BkTree {
+    pub(crate) fn new(episodes: &[Episode], matching: &MatchParams) -> Self {
+        let mut tree = BkTree {
+            damerau: matches!(matching.distance, Distance::Damerau),
+            ignore_words: matching.ignore_words.clone(),
+            nodes: vec![],
+        };
+        for (i, episode) in episodes.iter().enumerate() {
+            for name in episode.names() {
+                tree.insert(match_key(name, &matching.ignore_words), i);
+            }
+        }
+        tree
+    }
+
+    /// the closest of the episodes the tree was built from to some text and how far away it is,
+    /// the same one comparing against every episode would find, ties included
+    pub(crate) fn closest<'a>(&self, text: &str, episodes: &'a [Episode]) -> Option<(&'a Episode, f64)> {
+        let (distance, found) = self.nearest(&match_key(text, &self.ignore_words))?;
+        found.into_iter().map(|i| (&episodes[i], distance as f64)).min_by(by_distance)
+    }
+
+    fn measure(&self, a: &str, b: &str) -> usize {
+        if self.damerau { strsim::damerau_levenshtein(a, b) } else { strsim::levenshtein(a, b) }
+    }
+
+    fn insert(&mut self, key: String, episode: usize) {
+        if self.nodes.is_empty() {
+            self.nodes.push(Node { key, episodes: vec![episode], children: vec![] });
+            return;
+        }
+        let mut node = 0;
+        loop {
+            let distance = self.measure(&self.nodes[node].key, &key);
+            if distance == 0 {
+                self.nodes[node].episodes.push(episode);
+                return;
+            }
+            match self.nodes[node].children.iter().find(|(edge, _)| *edge == distance) {
+                Some(&(_, child)) => node = child,
+                None => {
+                    let child = self.nodes.len();
+                    self.nodes[node].children.push((distance, child));
+                    self.nodes.push(Node { key, episodes: vec![episode], children: vec![] });
+                    return;
+                }
+            }
+        }
+    }
+
+    // the smallest distance from the key to any name, and every episode with a name that close.
+    // None when there are no names
+    fn nearest(&self, key: &str) -> Option<(usize, Vec<usize>)> {
+        if self.nodes.is_empty() {
+            return None;
+        }
+        let mut best = usize::MAX;
+        let mut found = vec![];
+        let mut stack = vec![0];
+        while let Some(node) = stack.pop() {
+            let node = &self.nodes[node];
+            let distance = self.measure(&node.key, key);
+            if distance < best {
+                best = distance;
+                found.clear();
+            }
+            if distance == best {
+                found.extend(&node.episodes);
+            }
+            stack.extend(node.children.iter().filter(|(edge, _)| edge.abs_diff(distance) <= best).map(|(_, child)| *child));
+        }
+        Some((best, found))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::get_closest_episodes;
+
+    const SYLLABLES: [&str; 12] = ["bl", "ue", "y", "bin", "go", "ch", "ill", "i", "ba", "n", "dit", "mu"];
+
+    // a made-up name from a seed, so the same ones come out every run
+    fn name(seed: &mut u64, words: usize) -> String {
+        (0..words).map(|_| {
+            (0..2 + *seed as usize % 3).map(|_| {
+                *seed = seed.wrapping_mul(6364136223846793005).wrapping_add(1442695040888963407);
+                SYLLABLES[(*seed >> 33) as usize % SYLLABLES.len()]
+            }).collect::<String>()
+        }).collect::<Vec<_>>().join(" ")
+    }
+
+    #[test]
+    fn finds_what_comparing_every_episode_finds() {
+        let mut seed = 7;
+        let mut episodes = (0..600).map(|i| Episode {
+            name: name(&mut seed, 1 + i % 3),
+            season_and_episode: format!("S{:02}E{:02}", 1 + i / 52, 1 + i % 52),
+            aka: if i % 7 == 0 { vec![name(&mut seed, 2)] } else { vec![] },
+        }).collect::<Vec<_>>();
+        // the same name in several seasons, so there are exact ties to break
+        for i in 0..20 {
+            let episode = Episode { season_and_episode: format!("S20E{:02}", i + 1), ..episodes[i * 13].clone() };
+            episodes.push(episode);
+        }
+        let texts = (0..100).map(|i| name(&mut seed, 1 + i % 3))
+            .chain(episodes.iter().step_by(13).map(|episode| format!("{}x", &episode.name[1..])))
+            .collect::<Vec<_>>();
+
+        for distance in [Distance::Levenshtein, Distance::Damerau] {
+            let matching = MatchParams { distance, max_distance: None, ambiguity_margin: None, phonetic_tiebreak: false, overrides: None, ignore_words: vec!["the".to_string()] };
+            assert!(worth_building(&episodes, &matching));
+            let tree = BkTree::new(&episodes, &matching);
+            for text in &texts {
+                let (found, found_distance) = tree.closest(text, &episodes).unwrap();
+                let (expected, expected_distance) = get_closest_episodes(text, &episodes, &matching, 1)[0];
+                assert_eq!((&found.season_and_episode, found_distance), (&expected.season_and_episode, expected_distance), "{:?} {}", distance, text);
+            }
+        }
+    }
+}
@@ -0,0 +1,115 @@
+use std::collections::hash_map::DefaultHasher;
+use std::fs::File;
+use std::hash::{Hash, Hasher};
+use std::io::{BufReader, BufWriter};
+use std::path::{Path, PathBuf};
+use std::time::UNIX_EPOCH;
+use anyhow::{anyhow, Result};
+use image::RgbImage;
+use serde::{Deserialize, Serialize};
+use tracing::{debug, warn};
+use crate::{extract_frames, ExtractParams};
+
+/// title card frames found in earlier runs, so a video that hasn't changed isn't decoded again.
+/// each video gets a JSON sidecar and a PNG per frame, named by a hash of its path, size and
+/// modification time
+pub struct FrameCache {
+    dir: PathBuf,
+}
+
+// what's recorded about a video's frames next to the PNGs
+#[derive(Serialize, Deserialize)]
+struct Sidecar {
+    video: PathBuf,
+    // the detection settings the frames were found with, since different ones find other frames
+    settings: String,
+    frames: Vec<CachedFrame>,
+}
+
+#[derive(Serialize, Deserialize)]
+struct CachedFrame {
+    index: usize,
+    png: String,
+}
+
+impl FrameCache {
+    /// use `dir` for the cache, creating it if needed
+    pub fn open(dir: &Path) -> Result<Self> {
+        std::fs::create_dir_all(dir).map_err(|e| anyhow!("couldn't create cache directory {}: {}", dir.display(), e))?;
+        Ok(FrameCache { dir: dir.to_path_buf() })
+    }
+
+    /// the frames `extract_frames` finds in a video, read from the cache when they're there and
+    /// saved to it when they aren't. a cache that can't be read or written is only warned about
+    pub fn extract_frames(&self, filename: &Path, params: &ExtractParams) -> Result<Vec<(RgbImage, usize)>> {
+        let key = match cache_key(filename) {
+            Ok(key) => key,
+            Err(e) => {
+                warn!("not caching frames from {}: {}", filename.display(), e);
+                return extract_frames(filename, params);
+            }
+        };
+        let settings = settings(params);
+        match self.load(&key, &settings) {
+            Ok(Some(frames)) => {
+                debug!(video = %filename.display(), key, len = frames.len(), "using cached frames");
+                return Ok(frames);
+            }
+            Ok(None) => {}
+            Err(e) => warn!("ignoring cached frames for {}: {}", filename.display(), e),
+        }
+
+        let frames = extract_frames(filename, params)?;
+        if let Err(e) = self.store(&key, filename, settings, &frames) {
+            warn!("couldn't cache frames from {}: {}", filename.display(), e);
+        }
+        Ok(frames)
+    }
+
+    // the cached frames for a key, None when there aren't any or they were found with other settings
+    fn load(&self, key: &str, settings: &str) -> Result<Option<Vec<(RgbImage, usize)>>> {
+        let path = self.dir.join(format!("{}.json", key));
+        if !path.exists() {
+            return Ok(None);
+        }
+        let sidecar: Sidecar = serde_json::from_reader(BufReader::new(File::open(&path)?))?;
+        if sidecar.settings != settings {
+            return Ok(None);
+        }
+        let frames = sidecar.frames.iter()
+            .map(|frame| Ok((image::open(self.dir.join(&frame.png))?.into_rgb8(), frame.index)))
+            .collect::<Result<Vec<_>>>()?;
+        Ok(Some(frames))
+    }
+
+    fn store(&self, key: &str, video: &Path, settings: String, frames: &[(RgbImage, usize)]) -> Result<()> {
+        let mut cached = vec![];
+        for (i, (frame, index)) in frames.iter().enumerate() {
+            let png = format!("{}-{}.png", key, i);
+            frame.save(self.dir.join(&png))?;
+            cached.push(CachedFrame { index: *index, png });
+        }
+        // the sidecar goes last, so frames that weren't all written are never used
+        let sidecar = Sidecar { video: std::path::absolute(video)?, settings, frames: cached };
+        serde_json::to_writer_pretty(BufWriter::new(File::create(self.dir.join(format!("{}.json", key)))?), &sidecar)?;
+        Ok(())
+    }
+}
+
+// a name for a video that changes when it's moved, resized or modified. DefaultHasher isn't
+// guaranteed to be the same between Rust releases, which only costs a cache miss
+fn cache_key(filename: &Path) -> Result<String> {
+    let metadata = std::fs::metadata(filename)?;
+    let modified = metadata.modified()?.duration_since(UNIX_EPOCH)?;
+    let mut hasher = DefaultHasher::new();
+    std::path::absolute(filename)?.hash(&mut hasher);
+    metadata.len().hash(&mut hasher);
+    modified.as_nanos().hash(&mut hasher);
+    Ok(format!("{:016x}", hasher.finish()))
+}
+
+// the settings that decide which frames are found. --scan-jobs and --decode-threads only change
+// how fast
+fn settings(params: &ExtractParams) -> String {
+    format!("{:?}", ExtractParams { scan_jobs: 1, decode_threads: None, ..*params })
+}
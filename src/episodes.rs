@@ -0,0 +1,726 @@
+use std::cmp::Ordering;
+use std::collections::{BTreeSet, HashMap};
+use std::fs::File;
+use std::io::{BufReader, Cursor, Read};
+use std::path::Path;
+use std::sync::{Arc, LazyLock};
+use anyhow::{anyhow, Context, Result};
+use flate2::read::GzDecoder;
+use regex::{Regex, RegexBuilder};
+use serde::de::{SeqAccess, Visitor};
+use serde::{Deserialize, Deserializer, Serialize, Serializer};
+use tracing::{debug, warn};
+use crate::bktree::{self, BkTree};
+use crate::VideoNamerError;
+
+#[derive(Debug, Serialize, Deserialize, Clone)]
+pub struct Episode {
+    pub name: String,
+    #[serde(rename = "season")]
+    pub season_and_episode: String,
+    /// other titles the episode is known by, like a dub's. a CSV column separates them with |
+    #[serde(default, deserialize_with = "deserialize_aka", serialize_with = "serialize_aka")]
+    pub aka: Vec<String>,
+}
+
+impl Episode {
+    /// the episode's name followed by any other titles it's known by
+    pub fn names(&self) -> impl Iterator<Item = &str> {
+        std::iter::once(self.name.as_str()).chain(self.aka.iter().map(|aka| aka.as_str()))
+    }
+
+    /// the season number from a season_and_episode like S01E03
+    pub fn season(&self) -> Option<u32> {
+        parse_season_episode(&self.season_and_episode).map(|(season, _)| season)
+    }
+}
+
+// a JSON array of titles, or a string of them separated by | as a CSV has
+fn deserialize_aka<'de, D: Deserializer<'de>>(deserializer: D) -> Result<Vec<String>, D::Error> {
+    struct AkaVisitor;
+
+    impl<'de> Visitor<'de> for AkaVisitor {
+        type Value = Vec<String>;
+
+        fn expecting(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+            f.write_str("a list of titles or a string of titles separated by |")
+        }
+
+        fn visit_str<E: serde::de::Error>(self, value: &str) -> Result<Vec<String>, E> {
+            Ok(value.split('|').map(str::trim).filter(|aka| !aka.is_empty()).map(str::to_string).collect())
+        }
+
+        fn visit_seq<A: SeqAccess<'de>>(self, mut seq: A) -> Result<Vec<String>, A::Error> {
+            let mut akas = vec![];
+            while let Some(aka) = seq.next_element()? {
+                akas.push(aka);
+            }
+            Ok(akas)
+        }
+
+        fn visit_unit<E: serde::de::Error>(self) -> Result<Vec<String>, E> {
+            Ok(vec![])
+        }
+
+        // a CSV guesses that a title like "123" is a number
+        fn visit_u64<E: serde::de::Error>(self, value: u64) -> Result<Vec<String>, E> {
+            self.visit_str(&value.to_string())
+        }
+
+        fn visit_i64<E: serde::de::Error>(self, value: i64) -> Result<Vec<String>, E> {
+            self.visit_str(&value.to_string())
+        }
+
+        fn visit_f64<E: serde::de::Error>(self, value: f64) -> Result<Vec<String>, E> {
+            self.visit_str(&value.to_string())
+        }
+
+        fn visit_bool<E: serde::de::Error>(self, value: bool) -> Result<Vec<String>, E> {
+            self.visit_str(&value.to_string())
+        }
+    }
+
+    deserializer.deserialize_any(AkaVisitor)
+}
+
+// always written joined with | so it fits in a CSV column
+fn serialize_aka<S: Serializer>(aka: &[String], serializer: S) -> Result<S::Ok, S::Error> {
+    serializer.serialize_str(&aka.join("|"))
+}
+
+// \b doesn't count _ as a boundary, and filenames like Bluey_S01E03.mkv are common
+static SEASON_EPISODE: LazyLock<Regex> = LazyLock::new(|| Regex::new(r"(?i)(?:^|[^a-z0-9])S(\d{1,2})E(\d{1,3})").unwrap());
+
+/// the season and episode numbers from the first S01E03 style token in a string, like a filename
+pub fn parse_season_episode(filename: &str) -> Option<(u32, u32)> {
+    let captures = SEASON_EPISODE.captures(filename)?;
+    Some((captures[1].parse().ok()?, captures[2].parse().ok()?))
+}
+
+/// which CSV columns hold each episode's name and its season and episode. the defaults are what
+/// write_episode_names uses
+#[derive(Debug, Clone, Deserialize)]
+#[serde(default)]
+pub struct CsvColumns {
+    pub name: String,
+    pub season: String,
+}
+
+impl Default for CsvColumns {
+    fn default() -> Self {
+        CsvColumns { name: "name".to_string(), season: "season".to_string() }
+    }
+}
+
+/// load the episode database, leaving out episodes whose name matches any of `exclude`, like
+/// recaps, so they're never matched. a row that can't be read is an error, and so is a database
+/// with no episodes since nothing could ever match, while names used more than once are only
+/// warned about
+pub fn get_episode_names(path: &str, columns: &CsvColumns, exclude: &[Regex]) -> Result<Vec<Episode>> {
+    let (mut episodes, problems) = read_episodes(path, columns)?;
+    if let Some(problem) = problems.first() {
+        return Err(VideoNamerError::CsvParse { path: path.to_string(), message: problem.clone() }.into());
+    }
+    if episodes.is_empty() {
+        return Err(anyhow!("no episodes loaded from {}; check your CSV", path));
+    }
+    let loaded = episodes.len();
+    episodes.retain(|(_, episode)| !exclude.iter().any(|pattern| pattern.is_match(&episode.name)));
+    if episodes.is_empty() {
+        return Err(anyhow!("every episode in {} is excluded", path));
+    }
+    if episodes.len() < loaded {
+        debug!(excluded = loaded - episodes.len(), "excluded episodes from {}", path);
+    }
+    for duplicate in duplicate_names(&episodes) {
+        warn!("{}: {}", path, duplicate);
+    }
+    Ok(episodes.into_iter().map(|(_, episode)| episode).collect())
+}
+
+/// a pattern for excluding episodes by name, a regex that ignores case
+pub fn parse_exclude(pattern: &str) -> Result<Regex> {
+    Ok(RegexBuilder::new(pattern).case_insensitive(true).build()?)
+}
+
+// exclude patterns from a show profile, as a list of strings
+pub(crate) fn deserialize_exclude<'de, D: Deserializer<'de>>(deserializer: D) -> Result<Vec<Regex>, D::Error> {
+    Vec::<String>::deserialize(deserializer)?.iter()
+        .map(|pattern| parse_exclude(pattern).map_err(serde::de::Error::custom))
+        .collect()
+}
+
+/// every problem with the episode database at once: rows that can't be read, names used more
+/// than once, and having no episodes at all
+pub fn validate_episode_names(path: &str, columns: &CsvColumns) -> Result<Vec<String>> {
+    let (episodes, mut problems) = read_episodes(path, columns)?;
+    if episodes.is_empty() && problems.is_empty() {
+        problems.push("no episodes".to_string());
+    }
+    problems.extend(duplicate_names(&episodes));
+    Ok(problems)
+}
+
+// each episode that could be read along with where it came from, like "row 3" (counting from 1
+// after the header) or "season 2.csv row 3"
+type Rows = Vec<(String, Episode)>;
+
+// the episodes in a database, and a message for each row that couldn't be read. the database is
+// a CSV, a JSON array of objects with name and season keys, or a directory of CSVs. either file
+// can be gzipped or alone in a zip. `columns` only renames CSV columns
+fn read_episodes(path: &str, columns: &CsvColumns) -> Result<(Rows, Vec<String>)> {
+    if Path::new(path).is_dir() {
+        return read_csv_dir(path, columns);
+    }
+    let file = File::open(path).with_context(|| format!("episode database not found at {}", path))?;
+    let (extension, reader) = decompress(path, file)?;
+    match extension.as_deref() {
+        Some("csv") => read_csv(path, reader, columns),
+        Some("json") => read_json(path, reader),
+        _ => Err(anyhow!("unsupported episode database {}, expected a .csv or .json file, which can be in a .gz or a .zip", path)),
+    }
+}
+
+// the lowercased extension of the database inside a file, and a reader of it. "bluey.csv.gz" is
+// gunzipped and holds a CSV, a zip has to hold just one file and is named by it, and anything
+// else is read as it is
+fn decompress(path: &str, file: File) -> Result<(Option<String>, Box<dyn Read>)> {
+    let extension = |path: &Path| path.extension().map(|ext| ext.to_string_lossy().to_lowercase());
+    let path = Path::new(path);
+    match extension(path).as_deref() {
+        Some("gz") => {
+            let inner = path.file_stem().map(Path::new).and_then(extension);
+            Ok((inner, Box::new(GzDecoder::new(BufReader::new(file)))))
+        }
+        Some("zip") => {
+            let mut archive = zip::ZipArchive::new(BufReader::new(file)).map_err(|e| anyhow!("couldn't open {} as a zip: {}", path.display(), e))?;
+            if archive.len() != 1 {
+                return Err(anyhow!("{} holds {} files, expected just the episode database", path.display(), archive.len()));
+            }
+            let mut entry = archive.by_index(0)?;
+            let inner = extension(Path::new(entry.name()));
+            // the entry borrows the archive, so it's read up front
+            let mut contents = vec![];
+            entry.read_to_end(&mut contents).with_context(|| format!("couldn't unzip {} from {}", entry.name(), path.display()))?;
+            Ok((inner, Box::new(Cursor::new(contents))))
+        }
+        extension => Ok((extension.map(str::to_string), Box::new(file))),
+    }
+}
+
+fn read_csv(path: &str, file: impl Read, columns: &CsvColumns) -> Result<(Rows, Vec<String>)> {
+    let mut rdr = csv::Reader::from_reader(file);
+    let headers = rdr.headers().map_err(|e| VideoNamerError::CsvParse { path: path.to_string(), message: format!("couldn't read the header: {}", csv_error_message(&e)) })?.clone();
+    let headers = map_columns(path, &headers, columns)?;
+
+    let mut episodes = vec![];
+    let mut problems = vec![];
+    for (i, record) in rdr.records().enumerate() {
+        let row = i + 1;
+        match record.and_then(|record| record.deserialize::<Episode>(Some(&headers))) {
+            Ok(episode) => episodes.push((format!("row {}", row), episode)),
+            Err(e) => problems.push(format!("row {}: {}", row, csv_error_message(&e))),
+        }
+    }
+    Ok((episodes, problems))
+}
+
+// the header with the chosen name and season columns renamed to what Episode expects. any other
+// columns already called that are blanked so they can't be mistaken for them
+fn map_columns(path: &str, headers: &csv::StringRecord, columns: &CsvColumns) -> Result<csv::StringRecord> {
+    let find = |column: &str| headers.iter().position(|header| header.trim() == column).ok_or_else(|| VideoNamerError::CsvParse {
+        path: path.to_string(),
+        message: format!("no column named {:?}, the header has {}", column, headers.iter().collect::<Vec<_>>().join(", ")),
+    });
+    let (name, season) = (find(&columns.name)?, find(&columns.season)?);
+    Ok(headers.iter().enumerate().map(|(i, header)| match i {
+        _ if i == name => "name",
+        _ if i == season => "season",
+        _ if matches!(header.trim(), "name" | "season") => "",
+        _ => header,
+    }).collect())
+}
+
+// rows of a JSON array count from 1 like a CSV's
+fn read_json(path: &str, file: impl Read) -> Result<(Rows, Vec<String>)> {
+    let values: Vec<serde_json::Value> = serde_json::from_reader(BufReader::new(file))
+        .map_err(|e| anyhow!("{}: expected an array of episodes: {}", path, e))?;
+
+    let mut episodes = vec![];
+    let mut problems = vec![];
+    for (i, value) in values.into_iter().enumerate() {
+        let row = i + 1;
+        match serde_json::from_value::<Episode>(value) {
+            Ok(episode) => episodes.push((format!("row {}", row), episode)),
+            Err(e) => problems.push(format!("row {}: {}", row, e)),
+        }
+    }
+    Ok((episodes, problems))
+}
+
+static FILE_SEASON: LazyLock<Regex> = LazyLock::new(|| Regex::new(r"(?i)(?:^|[^a-z])(?:season|s)[ _-]*(\d{1,2})(?:\D|$)").unwrap());
+
+// every CSV in a directory, in filename order, as one database. when a file is named for a
+// season, like "Season 2.csv" or "bluey_s02.csv", rows that only number the episode ("3" or
+// "E03") are given that season
+fn read_csv_dir(dir: &str, columns: &CsvColumns) -> Result<(Rows, Vec<String>)> {
+    let mut files = std::fs::read_dir(dir)?.map(|entry| entry.map(|entry| entry.path())).collect::<std::io::Result<Vec<_>>>()?;
+    files.retain(|file| file.extension().is_some_and(|ext| ext.eq_ignore_ascii_case("csv")));
+    files.sort();
+    if files.is_empty() {
+        return Err(anyhow!("no .csv files in {}", dir));
+    }
+
+    let mut episodes = vec![];
+    let mut problems = vec![];
+    for file in files {
+        let name = file.file_name().unwrap_or_default().to_string_lossy().to_string();
+        let season = FILE_SEASON.captures(&name).and_then(|captures| captures[1].parse::<u32>().ok());
+        let (rows, file_problems) = read_csv(&file.to_string_lossy(), File::open(&file)?, columns)?;
+        episodes.extend(rows.into_iter().map(|(row, mut episode)| {
+            if let Some(season) = season {
+                tag_season(&mut episode, season);
+            }
+            (format!("{} {}", name, row), episode)
+        }));
+        problems.extend(file_problems.into_iter().map(|problem| format!("{} {}", name, problem)));
+    }
+    Ok((episodes, problems))
+}
+
+// turn an episode number on its own into a full S02E03 code in `season`
+fn tag_season(episode: &mut Episode, season: u32) {
+    if parse_season_episode(&episode.season_and_episode).is_some() {
+        return;
+    }
+    let number = episode.season_and_episode.trim();
+    let number = number.strip_prefix(['e', 'E']).unwrap_or(number);
+    if let Ok(number) = number.parse::<u32>() {
+        episode.season_and_episode = format!("S{:02}E{:02}", season, number);
+    }
+}
+
+// the useful part of a csv error, without the record and byte positions
+fn csv_error_message(e: &csv::Error) -> String {
+    match e.kind() {
+        csv::ErrorKind::Deserialize { err, .. } => err.to_string(),
+        csv::ErrorKind::UnequalLengths { expected_len, len, .. } => format!("expected {} fields, found {}", expected_len, len),
+        _ => e.to_string(),
+    }
+}
+
+// a message for each episode name that's already used by an earlier row, once normalized
+fn duplicate_names(episodes: &[(String, Episode)]) -> Vec<String> {
+    let mut seen: HashMap<String, &str> = HashMap::new();
+    let mut duplicates = vec![];
+    for (row, episode) in episodes {
+        if let Some(first) = seen.insert(normalize_title(&episode.name), row) {
+            duplicates.push(format!("{} and {} both have the name \"{}\"", first, row, episode.name));
+        }
+    }
+    duplicates
+}
+
+/// write episodes as a CSV that get_episode_names can read back
+pub fn write_episode_names(path: &str, episodes: &[Episode]) -> Result<()> {
+    let mut wtr = csv::Writer::from_path(path).map_err(|e| anyhow!("couldn't write {}: {}", path, e))?;
+    for episode in episodes {
+        wtr.serialize(episode)?;
+    }
+    wtr.flush()?;
+    Ok(())
+}
+
+// how OCR text is matched up with an episode name
+#[derive(clap::Args, Debug, Clone)]
+pub struct MatchParams {
+    /// string distance used to find the closest episode name
+    #[clap(long, global = true, value_enum, default_value_t = Distance::Levenshtein)]
+    pub distance: Distance,
+
+    /// refuse matches further away than this, in the units of --distance
+    #[clap(long, global = true)]
+    pub max_distance: Option<f64>,
+
+    /// warn when the runner-up episode is within this distance of the match, in the units of
+    /// --distance [default: 2 for levenshtein and damerau, 0.1 for the others, which only go from
+    /// 0 to 1]
+    #[clap(long, global = true)]
+    pub ambiguity_margin: Option<f64>,
+
+    /// when other episodes are within --ambiguity-margin of the closest, pick whichever sounds
+    /// most like the text
+    #[clap(long, global = true)]
+    pub phonetic_tiebreak: bool,
+
+    /// CSV of OCR text that's known to be misread, with a text column and an episode column
+    /// holding the episode's name or S01E03 number. text matching a row skips distance matching
+    #[clap(long, global = true, value_parser = load_overrides)]
+    pub overrides: Option<Overrides>,
+
+    /// words to leave out of both the OCR text and episode names before comparing them, like the
+    /// show's name or "the", comma separated and ignoring case
+    #[clap(long, global = true, value_delimiter = ',')]
+    pub ignore_words: Vec<String>,
+}
+
+/// OCR text pinned to the episode it really is, keyed by the normalized text
+#[derive(Debug, Clone, Default)]
+pub struct Overrides(Arc<HashMap<String, String>>);
+
+#[derive(Deserialize)]
+struct OverrideRow {
+    text: String,
+    episode: String,
+}
+
+/// read an overrides CSV with text and episode columns
+pub fn load_overrides(path: &str) -> Result<Overrides> {
+    let mut rdr = csv::Reader::from_path(path).map_err(|e| anyhow!("couldn't read overrides {}: {}", path, e))?;
+    let mut overrides = HashMap::new();
+    for (i, row) in rdr.deserialize::<OverrideRow>().enumerate() {
+        let row = row.map_err(|e| anyhow!("{}: row {}: {}", path, i + 1, csv_error_message(&e)))?;
+        overrides.insert(normalize_title(&row.text), row.episode);
+    }
+    Ok(Overrides(Arc::new(overrides)))
+}
+
+impl Overrides {
+    /// the episode some OCR text is pinned to, if it's pinned to one of `episodes`
+    pub fn get<'a>(&self, text: &str, episodes: &'a [Episode]) -> Option<&'a Episode> {
+        let target = self.0.get(&normalize_title(text))?;
+        let episode = episodes.iter().find(|episode| episode.season_and_episode.eq_ignore_ascii_case(target) || normalize_title(&episode.name) == normalize_title(target));
+        if episode.is_none() {
+            debug!(text, target, "override isn't one of the episodes being matched");
+        }
+        episode
+    }
+}
+
+impl MatchParams {
+    /// whether a match this far away is close enough to trust
+    pub fn is_confident(&self, distance: f64) -> bool {
+        self.max_distance.is_none_or(|max| distance <= max)
+    }
+
+    /// whether the runner-up is too close to the match to be sure which is right
+    pub fn is_ambiguous(&self, distance: f64, runner_up: f64) -> bool {
+        runner_up - distance <= self.ambiguity_margin.unwrap_or(self.distance.default_margin())
+    }
+}
+
+impl Distance {
+    /// how close a runner-up has to be for a match to be ambiguous when --ambiguity-margin isn't
+    /// given: a couple of edits, or a tenth for the distances that go from 0 to 1
+    pub fn default_margin(self) -> f64 {
+        match self {
+            Distance::Levenshtein | Distance::Damerau => 2.0,
+            Distance::JaroWinkler | Distance::NormalizedLevenshtein | Distance::TokenSet => 0.1,
+        }
+    }
+}
+
+#[derive(clap::ValueEnum, Debug, Clone, Copy)]
+pub enum Distance {
+    Levenshtein,
+    JaroWinkler,
+    Damerau,
+    NormalizedLevenshtein,
+    TokenSet,
+}
+
+// orders matches by distance, and episodes the same distance away by season and then episode
+// number, with unnumbered ones last, so the CSV's order never decides between them
+pub(crate) fn by_distance(a: &(&Episode, f64), b: &(&Episode, f64)) -> Ordering {
+    let number = |episode: &Episode| parse_season_episode(&episode.season_and_episode).unwrap_or((u32::MAX, u32::MAX));
+    a.1.total_cmp(&b.1).then_with(|| number(a.0).cmp(&number(b.0)))
+}
+
+/// episodes to match against, along with a BK-tree of their names when there are hundreds and
+/// `matching` uses levenshtein or damerau distances, so the closest can be looked up rather than
+/// found by comparing against each one. built once when the episodes are loaded, and only good
+/// for matching with the same `matching`
+pub struct EpisodeIndex<'a> {
+    episodes: &'a [Episode],
+    tree: Option<BkTree>,
+}
+
+impl<'a> EpisodeIndex<'a> {
+    pub fn new(episodes: &'a [Episode], matching: &MatchParams) -> Self {
+        let tree = bktree::worth_building(episodes, matching).then(|| BkTree::new(episodes, matching));
+        EpisodeIndex { episodes, tree }
+    }
+
+    pub fn episodes(&self) -> &'a [Episode] {
+        self.episodes
+    }
+}
+
+/// the episode whose name is closest to the OCR text, and how far away it is. text in
+/// `overrides` is matched to its pinned episode at distance 0 instead, and so is text that's an
+/// episode number like "Episode 12" rather than a title, to the episode with that number. with
+/// `phonetic_tiebreak`, the episodes within `ambiguity_margin` of the closest are compared by
+/// how they sound instead. episodes that are exactly as close as each other go to the lowest
+/// season, then the lowest episode, whatever order the CSV has them in. the index's BK-tree, when
+/// it has one, finds the closest without comparing against each episode
+pub fn get_corrected_episode_name<'a>(candiate_name: &str, index: &EpisodeIndex<'a>, matching: &MatchParams) -> Option<(&'a Episode, f64)> {
+    let episodes = index.episodes;
+    if let Some(episode) = matching.overrides.as_ref().and_then(|overrides| overrides.get(candiate_name, episodes)) {
+        return Some((episode, 0.0));
+    }
+    // an episode number needs comparing with every episode's number rather than its name
+    if let Some(tree) = index.tree.as_ref().filter(|_| numbered(candiate_name).is_none()) {
+        return tree.closest(candiate_name, episodes);
+    }
+    let distances = episodes.iter()
+        .map(|episode| (episode, distance_to(matching, episode, candiate_name)))
+        .collect::<Vec<_>>();
+    let closest = distances.iter().copied().min_by(by_distance)?;
+    if !matching.phonetic_tiebreak {
+        return Some(closest);
+    }
+
+    // only a near tie by the distance itself is left to how they sound
+    let tied = distances.into_iter().filter(|(_, distance)| matching.is_ambiguous(closest.1, *distance)).collect::<Vec<_>>();
+    if tied.len() < 2 {
+        return Some(closest);
+    }
+    let sound = phonetic_key(candiate_name, &matching.ignore_words);
+    let sounds_off = |episode: &Episode| episode.names().map(|name| strsim::levenshtein(&phonetic_key(name, &matching.ignore_words), &sound)).min().unwrap_or(usize::MAX);
+    tied.into_iter().min_by(|a, b| sounds_off(a.0).cmp(&sounds_off(b.0)).then_with(|| by_distance(a, b)))
+}
+
+/// the `n` episodes whose names are closest to the OCR text, closest first, with ties ordered as
+/// `get_corrected_episode_name` breaks them
+pub fn get_closest_episodes<'a>(candiate_name: &str, episodes: &'a [Episode], matching: &MatchParams, n: usize) -> Vec<(&'a Episode, f64)> {
+    let mut matches = episodes.iter()
+        .map(|episode| (episode, distance_to(matching, episode, candiate_name)))
+        .collect::<Vec<_>>();
+    matches.sort_by(by_distance);
+    matches.truncate(n);
+    matches
+}
+
+/// a title reduced to what matters for matching: lowercase, single spaces, and no punctuation or
+/// whitespace at either end, so "  Sleepytime. " and "sleepytime" are the same
+pub fn normalize_title(title: &str) -> String {
+    title.split_whitespace()
+        .collect::<Vec<_>>()
+        .join(" ")
+        .trim_matches(|c: char| !c.is_alphanumeric())
+        .to_lowercase()
+}
+
+// a normalized title without any of `ignore_words`. a title made only of ignored words is kept
+// whole, so there's still something to compare
+pub(crate) fn match_key(title: &str, ignore_words: &[String]) -> String {
+    let title = normalize_title(title);
+    let ignored = |word: &str| ignore_words.iter().any(|ignore| word.trim_matches(|c: char| !c.is_alphanumeric()).eq_ignore_ascii_case(ignore.trim()));
+    let kept = title.split(' ').filter(|word| !ignored(word)).collect::<Vec<_>>();
+    if kept.is_empty() { title } else { kept.join(" ") }
+}
+
+// how far the text is from whichever of the episode's names it's closest to, or 0 when the text
+// is the episode's number
+fn distance_to(matching: &MatchParams, episode: &Episode, text: &str) -> f64 {
+    if let (Some((season, number)), Some((episode_season, episode_number))) = (numbered(text), parse_season_episode(&episode.season_and_episode)) {
+        if number == episode_number && season.is_none_or(|season| season == episode_season) {
+            return 0.0;
+        }
+    }
+    episode.names().map(|name| episode_distance(matching, name, text)).fold(f64::INFINITY, f64::min)
+}
+
+static EPISODE_NUMBER: LazyLock<Regex> = LazyLock::new(|| Regex::new(r"^(?:(?:season|s) ?(\d{1,2}),? ?)?(?:episode|ep\.?|no\.?|e)? ?(\d{1,3})$").unwrap());
+
+// the season, when there is one, and episode number that OCR text is when it's a number rather
+// than a title, like "Episode 12", "#12", "Season 2 Episode 5" or "S02E05". with several
+// seasons, a number on its own matches that episode of each, and the closest ones tie
+fn numbered(text: &str) -> Option<(Option<u32>, u32)> {
+    let text = normalize_title(text);
+    let captures = EPISODE_NUMBER.captures(&text)?;
+    let season = captures.get(1).and_then(|season| season.as_str().parse().ok());
+    Some((season, captures[2].parse().ok()?))
+}
+
+// how far apart two names are once normalized and stripped of --ignore-words, lower is closer.
+// jaro-winkler, normalized levenshtein and token set are similarities in [0, 1] where higher is
+// closer, so those are turned into 1 - similarity
+fn episode_distance(matching: &MatchParams, a: &str, b: &str) -> f64 {
+    let (a, b) = (&match_key(a, &matching.ignore_words), &match_key(b, &matching.ignore_words));
+    match matching.distance {
+        Distance::Levenshtein => strsim::levenshtein(a, b) as f64,
+        Distance::Damerau => strsim::damerau_levenshtein(a, b) as f64,
+        Distance::JaroWinkler => 1.0 - strsim::jaro_winkler(a, b),
+        Distance::NormalizedLevenshtein => 1.0 - strsim::normalized_levenshtein(a, b),
+        Distance::TokenSet => 1.0 - token_set_ratio(a, b),
+    }
+}
+
+// how alike two titles are as sets of words, so word order and extra or missing words count for
+// little: the words both share, compared with the shared words plus each side's leftovers
+fn token_set_ratio(a: &str, b: &str) -> f64 {
+    let a_words = a.split(' ').collect::<BTreeSet<_>>();
+    let b_words = b.split(' ').collect::<BTreeSet<_>>();
+    let join = |words: Vec<&str>| words.join(" ").trim().to_string();
+    let shared = join(a_words.intersection(&b_words).copied().collect());
+    let with_a = join(vec![shared.as_str(), &join(a_words.difference(&b_words).copied().collect())]);
+    let with_b = join(vec![shared.as_str(), &join(b_words.difference(&a_words).copied().collect())]);
+    [(&shared, &with_a), (&shared, &with_b), (&with_a, &with_b)].iter()
+        .filter(|(x, y)| !x.is_empty() || !y.is_empty())
+        .map(|(x, y)| strsim::normalized_levenshtein(x, y))
+        .fold(0.0, f64::max)
+}
+
+// a title as it sounds: the soundex code of each word, so "Bingo" and "Bimgo" come out the same
+fn phonetic_key(title: &str, ignore_words: &[String]) -> String {
+    match_key(title, ignore_words).split(' ').map(soundex).collect::<Vec<_>>().join(" ")
+}
+
+// the first letter of a word followed by up to three digits for the consonant sounds after it.
+// words without letters, like numbers, are left as they are
+fn soundex(word: &str) -> String {
+    let code = |c: char| match c {
+        'b' | 'f' | 'p' | 'v' => Some('1'),
+        'c' | 'g' | 'j' | 'k' | 'q' | 's' | 'x' | 'z' => Some('2'),
+        'd' | 't' => Some('3'),
+        'l' => Some('4'),
+        'm' | 'n' => Some('5'),
+        'r' => Some('6'),
+        _ => None,
+    };
+    let mut letters = word.chars().filter(|c| c.is_ascii_alphabetic());
+    let Some(first) = letters.next() else {
+        return word.to_string();
+    };
+    let mut key = first.to_ascii_uppercase().to_string();
+    let mut last = code(first);
+    for c in letters {
+        let digit = code(c);
+        if digit.is_some() && digit != last {
+            key.extend(digit);
+            if key.len() == 4 {
+                break;
+            }
+        }
+        // h and w don't separate two letters with the same code, vowels do
+        if c != 'h' && c != 'w' {
+            last = digit;
+        }
+    }
+    format!("{:0<4}", key)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn episode(name: &str, season: &str) -> Episode {
+        Episode { name: name.to_string(), season_and_episode: season.to_string(), aka: vec![] }
+    }
+
+    fn matching(distance: Distance) -> MatchParams {
+        MatchParams { distance, max_distance: None, ambiguity_margin: None, phonetic_tiebreak: false, overrides: None, ignore_words: vec![] }
+    }
+
+    #[test]
+    fn ambiguity_margin_depends_on_distance() {
+        assert!(matching(Distance::Levenshtein).is_ambiguous(1.0, 3.0));
+        assert!(!matching(Distance::Levenshtein).is_ambiguous(1.0, 4.0));
+        // a clear winner on a 0 to 1 scale isn't ambiguous
+        for distance in [Distance::JaroWinkler, Distance::NormalizedLevenshtein, Distance::TokenSet] {
+            assert!(!matching(distance).is_ambiguous(0.05, 0.5));
+            assert!(matching(distance).is_ambiguous(0.05, 0.1));
+        }
+        let given = MatchParams { ambiguity_margin: Some(0.5), ..matching(Distance::JaroWinkler) };
+        assert!(given.is_ambiguous(0.05, 0.5));
+    }
+
+    #[test]
+    fn phonetic_tiebreak_only_decides_near_ties() {
+        // "Kp Up" sounds exactly like the text but is far from it by the distance
+        let episodes = [episode("Keepy Upsy", "S01E01"), episode("Kp Up", "S01E02")];
+        for distance in [Distance::Levenshtein, Distance::JaroWinkler, Distance::NormalizedLevenshtein] {
+            let matching = MatchParams { phonetic_tiebreak: true, ..matching(distance) };
+            let (found, _) = get_corrected_episode_name("Keepy Upy", &EpisodeIndex::new(&episodes, &matching), &matching).unwrap();
+            assert_eq!(found.name, "Keepy Upsy", "{:?}", distance);
+        }
+        // within a couple of edits of each other, the one that sounds right wins
+        let episodes = [episode("Bimbo", "S01E01"), episode("Bingo", "S01E02")];
+        let matching = MatchParams { phonetic_tiebreak: true, ..matching(Distance::Levenshtein) };
+        assert_eq!(get_corrected_episode_name("Bimgo", &EpisodeIndex::new(&episodes, &matching), &matching).unwrap().0.name, "Bingo");
+    }
+
+    #[test]
+    fn season_episode_after_any_separator() {
+        for filename in ["Bluey_S01E03.mkv", "Bluey.S01E03.mkv", "Bluey S01E03.mkv", "s01e03.mkv"] {
+            assert_eq!(parse_season_episode(filename), Some((1, 3)), "{}", filename);
+        }
+        assert_eq!(parse_season_episode("BlueyS01E03.mkv"), None);
+    }
+
+    #[test]
+    fn normalized_levenshtein_picks_the_closest() {
+        let episodes = [episode("Magic Xylophone", "S01E01"), episode("Hospital", "S01E02"), episode("Keepy Uppy", "S01E04"), episode("Daddy Robot", "S01E05")];
+        let matching = matching(Distance::NormalizedLevenshtein);
+        let index = EpisodeIndex::new(&episodes, &matching);
+        assert_eq!(get_corrected_episode_name("Hospita1", &index, &matching).unwrap().0.name, "Hospital");
+        assert_eq!(get_corrected_episode_name("KEEPY UPY!", &index, &matching).unwrap().0.name, "Keepy Uppy");
+        assert_eq!(get_corrected_episode_name("Daddy Rob0t", &index, &matching).unwrap().0.name, "Daddy Robot");
+    }
+
+    #[test]
+    fn normalize_title_keeps_inner_apostrophes() {
+        assert_eq!(normalize_title("Bingo's Birthday  "), "bingo's birthday");
+        assert_eq!(normalize_title("  'Grannies' "), "grannies");
+        assert_eq!(normalize_title("Mum  School\n"), "mum school");
+        assert_eq!(normalize_title("Mum School"), normalize_title(" mum school. "));
+    }
+
+    #[test]
+    fn empty_episode_database_is_an_error() {
+        let path = std::env::temp_dir().join(format!("video_namer-{}-empty.csv", std::process::id()));
+        // a header and no rows
+        std::fs::write(&path, "name,season\n").unwrap();
+        let result = get_episode_names(path.to_str().unwrap(), &CsvColumns::default(), &[]);
+        std::fs::remove_file(&path).unwrap();
+        let e = result.unwrap_err();
+        assert!(e.to_string().contains("no episodes loaded"), "{}", e);
+    }
+
+    #[test]
+    fn token_set_ignores_word_order_and_dropped_words() {
+        assert_eq!(token_set_ratio("magic dog", "dog magic"), 1.0);
+        assert_eq!(token_set_ratio("xylophone", "magic xylophone"), 1.0);
+        assert!(token_set_ratio("magic dog", "daddy robot") < 0.5);
+        // plain edit distance makes a lot of the same reordering
+        assert!(strsim::normalized_levenshtein("magic dog", "dog magic") < 0.5);
+
+        let episodes = [episode("Magic Xylophone", "S01E01"), episode("Daddy Robot", "S01E05")];
+        let matching = matching(Distance::TokenSet);
+        let (found, distance) = get_corrected_episode_name("Xylophone Magic", &EpisodeIndex::new(&episodes, &matching), &matching).unwrap();
+        assert_eq!((found.name.as_str(), distance), ("Magic Xylophone", 0.0));
+    }
+
+    #[test]
+    fn match_key_drops_ignored_words() {
+        assert_eq!(match_key("The Creek", &[]), "the creek");
+        assert_eq!(match_key("The Creek", &["the".to_string()]), "creek");
+        assert_eq!(match_key("Bluey: The Sign", &["bluey".to_string(), "The".to_string()]), "sign");
+        // with nothing left, the title is kept whole
+        assert_eq!(match_key("The", &["the".to_string()]), "the");
+    }
+
+    #[test]
+    fn clear_match_isnt_ambiguous_on_a_zero_to_one_scale() {
+        let episodes = [episode("Sleepytime", "S02E26"), episode("Sticky Gecko", "S02E12"), episode("Shadowlands", "S02E09")];
+        for distance in [Distance::JaroWinkler, Distance::NormalizedLevenshtein] {
+            let matching = matching(distance);
+            let [(best, distance), (_, runner_up)] = get_closest_episodes("Sleepytlme", &episodes, &matching, 2)[..] else {
+                panic!("expected two matches");
+            };
+            assert_eq!(best.name, "Sleepytime");
+            assert!(!matching.is_ambiguous(distance, runner_up));
+        }
+    }
+}
@@ -0,0 +1,31 @@
+use std::path::PathBuf;
+use thiserror::Error;
+
+/// the ways reading a video or a title card can fail that are worth telling apart. library
+/// functions return these inside anyhow errors, so match on them with `downcast_ref`
+#[derive(Debug, Error)]
+pub enum VideoNamerError {
+    #[error("no video stream in {0}")]
+    NoVideoStream(String),
+
+    #[error("no title card found in {0}")]
+    NoBlueFrame(String),
+
+    #[error("No text detected")]
+    NoTextDetected,
+
+    #[error("No episode found")]
+    NoEpisodeFound,
+
+    /// the closest episode was further away than --max-distance
+    #[error("no confident match, the closest episode is {name} at distance {distance}")]
+    NoConfidentMatch { name: String, distance: f64 },
+
+    /// a row of the episode database couldn't be read
+    #[error("{path}: {message}")]
+    CsvParse { path: String, message: String },
+
+    /// the detection or recognition model was missing or couldn't be loaded
+    #[error("{kind} model not found or not loadable at {}: {message}", path.display())]
+    ModelLoad { kind: &'static str, path: PathBuf, message: String },
+}
@@ -0,0 +1,74 @@
+use anyhow::{anyhow, Result};
+use serde::de::DeserializeOwned;
+use serde::Deserialize;
+use tracing::{debug, info};
+use crate::Episode;
+
+const TMDB: &str = "https://api.themoviedb.org/3";
+
+#[derive(Deserialize)]
+struct SearchResults {
+    results: Vec<SearchResult>,
+}
+
+#[derive(Deserialize)]
+struct SearchResult {
+    id: u64,
+    name: String,
+}
+
+#[derive(Deserialize)]
+struct Series {
+    seasons: Vec<SeasonSummary>,
+}
+
+#[derive(Deserialize)]
+struct SeasonSummary {
+    season_number: u32,
+}
+
+#[derive(Deserialize)]
+struct Season {
+    episodes: Vec<TmdbEpisode>,
+}
+
+#[derive(Deserialize)]
+struct TmdbEpisode {
+    name: String,
+    season_number: u32,
+    episode_number: u32,
+}
+
+/// look a show up on TMDB and list its episodes, with seasons and episodes numbered like S01E03.
+/// season 0 holds the specials, which are left out unless `specials` is set
+pub fn fetch_episodes(show: &str, api_key: &str, specials: bool) -> Result<Vec<Episode>> {
+    let search: SearchResults = tmdb_get("/search/tv", api_key, &[("query", show)])?;
+    let series = search.results.first().ok_or(anyhow!("no show called {} found on TMDB", show))?;
+    info!(id = series.id, name = series.name, "found show");
+
+    let details: Series = tmdb_get(&format!("/tv/{}", series.id), api_key, &[])?;
+    let mut episodes = vec![];
+    for summary in details.seasons.iter().filter(|s| specials || s.season_number > 0) {
+        let season: Season = tmdb_get(&format!("/tv/{}/season/{}", series.id, summary.season_number), api_key, &[])?;
+        debug!(season = summary.season_number, len = season.episodes.len(), "fetched season");
+        episodes.extend(season.episodes.into_iter().map(|episode| Episode {
+            name: episode.name,
+            season_and_episode: format!("S{:02}E{:02}", episode.season_number, episode.episode_number),
+            aka: vec![],
+        }));
+    }
+    Ok(episodes)
+}
+
+// GET a TMDB endpoint and decode its JSON, turning HTTP and network failures into errors that
+// say what went wrong
+fn tmdb_get<T: DeserializeOwned>(path: &str, api_key: &str, query: &[(&str, &str)]) -> Result<T> {
+    let url = format!("{}{}", TMDB, path);
+    let request = query.iter().fold(ureq::get(&url).query("api_key", api_key), |request, (k, v)| request.query(k, v));
+    let response = request.call().map_err(|e| match e {
+        ureq::Error::Status(401, _) => anyhow!("TMDB rejected the API key"),
+        ureq::Error::Status(code, response) => anyhow!("TMDB returned {} {} for {}", code, response.status_text(), path),
+        ureq::Error::Transport(e) => anyhow!("couldn't reach TMDB: {}", e),
+    })?;
+    response.into_json().map_err(|e| anyhow!("unexpected response from TMDB for {}: {}", path, e))
+}
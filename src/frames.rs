@@ -0,0 +1,798 @@
+use std::path::Path;
+use std::str::FromStr;
+use anyhow::{anyhow, Result};
+use ffmpeg_next::codec;
+use ffmpeg_next::media::Type;
+use ffmpeg_next::util::frame::video::Video;
+use ffmpeg_next::{Rational, Rescale};
+use image::{ImageBuffer, RgbImage};
+use indicatif::ProgressBar;
+use rayon::prelude::*;
+use serde::Deserialize;
+use tracing::{debug, info};
+use crate::{Roi, VideoNamerError, PROGRESS};
+
+const FPS: usize = 24;
+
+// where and how often to look for a title card. both are counted in frames, so the time they
+// cover depends on the video's frame rate: at 24fps the defaults skip the first 28 seconds and
+// then check one frame a second
+#[derive(clap::Args, Debug, Clone, Copy)]
+pub struct ExtractParams {
+    /// number of frames to skip before looking for a title card (seconds = frames / fps). with
+    /// --from-end they're skipped from the end instead
+    #[clap(long, default_value_t = 28 * FPS)]
+    pub skip_frames: usize,
+
+    /// only check every Nth frame after skipping (N = fps checks once a second)
+    #[clap(long, default_value_t = FPS, value_parser = clap::builder::RangedU64ValueParser::<usize>::new().range(1..))]
+    pub sample_every: usize,
+
+    /// collect up to N title card candidates and keep the one whose text best matches an episode
+    #[clap(long, default_value_t = 1, value_parser = clap::builder::RangedU64ValueParser::<usize>::new().range(1..))]
+    pub candidates: usize,
+
+    /// give up after checking this many sampled frames rather than scanning to the end
+    #[clap(long)]
+    pub max_samples: Option<usize>,
+
+    /// start looking for a title card at this time (SS, MM:SS or HH:MM:SS) instead of after
+    /// --skip-frames
+    #[clap(long, value_parser = parse_timestamp, conflicts_with = "skip_frames")]
+    pub scan_start: Option<f64>,
+
+    /// stop looking for a title card after this time (SS, MM:SS or HH:MM:SS), even if none was found
+    #[clap(long, value_parser = parse_timestamp)]
+    pub scan_end: Option<f64>,
+
+    /// when no frame is blue enough, use the bluest one seen instead of giving up
+    #[clap(long)]
+    pub fallback_best: bool,
+
+    /// look for the title card backwards from the end of the video (or from --scan-end back to
+    /// --scan-start), for shows that put it late or in the outro
+    #[clap(long)]
+    pub from_end: bool,
+
+    /// score this many sampled frames at once on separate threads. decoding stays on one thread,
+    /// and up to N - 1 frames past the title card may be decoded for nothing
+    #[clap(long, default_value_t = 1, value_parser = clap::builder::RangedU64ValueParser::<usize>::new().range(1..))]
+    pub scan_jobs: usize,
+
+    /// when no frame is blue enough, look again with looser thresholds before giving up
+    #[clap(long)]
+    pub retry_relaxed: bool,
+
+    /// only look at every Kth pixel when working out how blue a frame is. since that's a share
+    /// of the pixels, a few of them give nearly the same answer as all of them, much faster on
+    /// large videos. 1 looks at every pixel
+    #[clap(long, default_value_t = 1, value_parser = clap::builder::RangedU64ValueParser::<usize>::new().range(1..))]
+    pub pixel_stride: usize,
+
+    /// only count pixels in this part of each frame when working out how blue it is, given as
+    /// x,y,width,height fractions of the frame's size like 0.2,0.2,0.6,0.6 for the middle 60%.
+    /// for title cards whose colour fills the middle while the edges are busy
+    #[clap(long)]
+    pub color_sample_region: Option<Roi>,
+
+    /// how frames are converted to RGB. lanczos and bicubic can make small text easier to read,
+    /// point is the cheapest
+    #[clap(long, value_enum, default_value_t = Scaler::Bilinear)]
+    pub scaler: Scaler,
+
+    /// threads ffmpeg decodes each video with [default: as many as it decides on, usually one per
+    /// CPU]. each of the files --jobs works on at once gets its own decoder with this many
+    /// threads, so with several jobs 1 avoids starting far more threads than there are CPUs
+    #[clap(long, value_parser = clap::builder::RangedU64ValueParser::<usize>::new().range(1..))]
+    pub decode_threads: Option<usize>,
+
+    // comes from the show profile rather than its own flags
+    #[clap(skip)]
+    pub thresholds: ColorThresholds,
+}
+
+impl ExtractParams {
+    /// an error when --scan-start isn't before --scan-end
+    pub fn check_scan_window(&self) -> Result<()> {
+        match (self.scan_start, self.scan_end) {
+            (Some(start), Some(end)) if start >= end => Err(anyhow!("--scan-start ({}s) must be before --scan-end ({}s)", start, end)),
+            _ => Ok(()),
+        }
+    }
+}
+
+/// how a title card is recognised. with the rgb detector each pixel is an 8-bit RGB triple and
+/// counts as blue when b > min_b, r < max_r and g < max_g (all strict). with the hsv detector a
+/// pixel counts as blue when its hue is between min_hue and max_hue degrees and its saturation
+/// and value (0.0 to 1.0) are at least min_saturation and min_value, which copes better with
+/// brightness and grading differences between encodes. the color detector is for shows whose
+/// title cards aren't blue: a pixel counts when each channel is within color_tolerance of
+/// target_color. whichever is used, a frame is a title card when more than min_ratio (0.0 to
+/// 1.0) of its pixels count. the defaults are tuned for Bluey's title card.
+#[derive(Debug, Deserialize, Clone, Copy)]
+#[serde(default)]
+pub struct ColorThresholds {
+    pub detector: Detector,
+    pub min_b: u8,
+    pub max_r: u8,
+    pub max_g: u8,
+    pub min_hue: f64,
+    pub max_hue: f64,
+    pub min_saturation: f64,
+    pub min_value: f64,
+    pub target_color: Color,
+    pub color_tolerance: u8,
+    pub min_ratio: f64,
+}
+
+impl Default for ColorThresholds {
+    fn default() -> Self {
+        ColorThresholds {
+            detector: Detector::Rgb,
+            min_b: 220,
+            max_r: 180,
+            max_g: 235,
+            min_hue: 180.0,
+            max_hue: 240.0,
+            min_saturation: 0.25,
+            min_value: 0.75,
+            target_color: Color([120, 200, 240]),
+            color_tolerance: 40,
+            min_ratio: 0.8,
+        }
+    }
+}
+
+// how many extra passes --retry-relaxed makes, each looser than the last
+const RELAXED_PASSES: u32 = 2;
+
+impl ColorThresholds {
+    /// these thresholds loosened `pass` steps: a lower min_ratio and wider colour bounds for
+    /// whichever detector is used. pass 0 leaves them as they are
+    pub fn relaxed(&self, pass: u32) -> Self {
+        let step = pass as f64;
+        let widen = (pass * 15).min(255) as u8;
+        ColorThresholds {
+            min_b: self.min_b.saturating_sub(widen),
+            max_r: self.max_r.saturating_add(widen),
+            max_g: self.max_g.saturating_add(widen),
+            min_hue: self.min_hue - 10.0 * step,
+            max_hue: self.max_hue + 10.0 * step,
+            min_saturation: (self.min_saturation - 0.05 * step).max(0.0),
+            min_value: (self.min_value - 0.05 * step).max(0.0),
+            color_tolerance: self.color_tolerance.saturating_add(widen),
+            min_ratio: self.min_ratio * (1.0 - 0.15 * step).max(0.0),
+            ..*self
+        }
+    }
+}
+
+/// which rule decides whether a pixel is blue
+#[derive(clap::ValueEnum, Debug, Deserialize, Clone, Copy, PartialEq)]
+#[serde(rename_all = "lowercase")]
+pub enum Detector {
+    Rgb,
+    Hsv,
+    Color,
+}
+
+/// the scaling algorithm ffmpeg uses when converting decoded frames to RGB
+#[derive(clap::ValueEnum, Debug, Clone, Copy, PartialEq)]
+pub enum Scaler {
+    Bilinear,
+    Lanczos,
+    Point,
+    Bicubic,
+}
+
+impl Scaler {
+    fn flags(self) -> ffmpeg_next::software::scaling::flag::Flags {
+        use ffmpeg_next::software::scaling::flag::Flags;
+        match self {
+            Scaler::Bilinear => Flags::BILINEAR,
+            Scaler::Lanczos => Flags::LANCZOS,
+            Scaler::Point => Flags::POINT,
+            Scaler::Bicubic => Flags::BICUBIC,
+        }
+    }
+}
+
+/// an RGB colour, written as RRGGBB hex like a web colour
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Color(pub [u8; 3]);
+
+impl FromStr for Color {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> Result<Self> {
+        let hex = s.strip_prefix('#').unwrap_or(s);
+        if hex.len() != 6 || !hex.is_ascii() {
+            return Err(anyhow!("expected a colour as RRGGBB, got {}", s));
+        }
+        let channel = |i: usize| u8::from_str_radix(&hex[i..i + 2], 16).map_err(|_| anyhow!("expected a colour as RRGGBB, got {}", s));
+        Ok(Color([channel(0)?, channel(2)?, channel(4)?]))
+    }
+}
+
+impl<'de> Deserialize<'de> for Color {
+    fn deserialize<D: serde::Deserializer<'de>>(deserializer: D) -> std::result::Result<Self, D::Error> {
+        String::deserialize(deserializer)?.parse().map_err(serde::de::Error::custom)
+    }
+}
+
+/// decode a video and collect up to `params.candidates` frames that look like a title card. with
+/// `params.retry_relaxed` the video is scanned again with looser thresholds when nothing is found,
+/// which means decoding it again each time
+pub fn extract_frames(filename: &Path, params: &ExtractParams) -> Result<Vec<(RgbImage, usize)>> {
+    let passes = if params.retry_relaxed { RELAXED_PASSES } else { 0 };
+    for pass in 0..=passes {
+        // only fall back to the bluest frame once the loosest pass has found nothing either
+        let pass_params = ExtractParams {
+            thresholds: params.thresholds.relaxed(pass),
+            fallback_best: params.fallback_best && pass == passes,
+            ..*params
+        };
+        let mut found = vec![];
+        visit_title_cards(filename, &pass_params, |img, frame_index| {
+            found.push((img, frame_index));
+            Ok(found.len() < params.candidates)
+        })?;
+        if !found.is_empty() {
+            if pass > 0 {
+                info!(pass, "found a title card in {} with relaxed thresholds", filename.display());
+            }
+            return Ok(found);
+        }
+        if pass < passes {
+            info!(pass = pass + 1, "no title card in {}, trying again with relaxed thresholds", filename.display());
+        }
+    }
+    Ok(vec![])
+}
+
+/// call `visit` with each sampled frame that looks like a title card and its frame index, until
+/// it returns false or the video ends. rather than decoding every frame, this seeks to each
+/// sampled time and decodes from the keyframe before it, so the frame counts in `params` are
+/// turned into times with the stream's frame rate
+pub fn visit_title_cards(filename: &Path, params: &ExtractParams, mut visit: impl FnMut(RgbImage, usize) -> Result<bool>) -> Result<()> {
+    let mut frames = SampledFrames::open(filename, params)?;
+
+    let bar = PROGRESS.add(match frames.total_frames() {
+        Some(total) => ProgressBar::new(total),
+        None => ProgressBar::new_spinner(),
+    });
+
+    let pool = match params.scan_jobs {
+        1 => None,
+        jobs => Some(rayon::ThreadPoolBuilder::new().num_threads(jobs).build()?),
+    };
+
+    let mut visited = false;
+    // the bluest frame that didn't make the threshold, for --fallback-best
+    let mut bluest: Option<(RgbImage, usize, f64)> = None;
+    let mut best_score: f64 = 0.0;
+    'scan: loop {
+        // the decoder can only be used from one thread, so frames are decoded a batch at a time
+        // and only scored in parallel
+        let mut batch = vec![];
+        while batch.len() < params.scan_jobs {
+            match frames.next_frame()? {
+                Some(frame) => batch.push(frame),
+                None => break,
+            }
+        }
+        if batch.is_empty() {
+            break;
+        }
+        let score = |(rgb_frame, _): &(Video, usize)| blue_score(rgb_frame, params);
+        let scores = match &pool {
+            Some(pool) => pool.install(|| batch.par_iter().map(score).collect::<Vec<_>>()),
+            None => batch.iter().map(score).collect(),
+        };
+
+        // most frames aren't title cards, so only copy a frame into an image once it's wanted
+        for ((rgb_frame, frame_index), score) in batch.into_iter().zip(scores) {
+            bar.set_position(frame_index as u64);
+            best_score = best_score.max(score);
+            if score > params.thresholds.min_ratio {
+                debug!(frame_index, score, "found a blue frame");
+                visited = true;
+                if !visit(to_image(&rgb_frame)?, frame_index)? {
+                    break 'scan;
+                }
+            } else if params.fallback_best && bluest.as_ref().is_none_or(|(_, _, best)| score > *best) {
+                bluest = Some((to_image(&rgb_frame)?, frame_index, score));
+            }
+        }
+    }
+    bar.finish_and_clear();
+    info!(samples = frames.samples, best_score, "checked {}", filename.display());
+
+    if let Some((img, frame_index, score)) = bluest.filter(|_| !visited) {
+        info!(frame_index, score, "no frame was blue enough, falling back to the bluest");
+        visit(img, frame_index)?;
+    }
+    Ok(())
+}
+
+/// the frames of a video picked out by `ExtractParams`' skip_frames (or scan_start), sample_every,
+/// scan_end, max_samples and from_end, decoded one at a time as they're asked for, with their
+/// frame indexes. whether they look like a title card is left to the caller
+pub struct SampledFrames {
+    video: VideoFile,
+    stride: f64,
+    seconds: f64,
+    // going backwards, sampling stops before start rather than after end
+    backward: bool,
+    start: f64,
+    end: Option<f64>,
+    samples: usize,
+    max_samples: Option<usize>,
+}
+
+impl SampledFrames {
+    pub fn open(filename: &Path, params: &ExtractParams) -> Result<Self> {
+        params.check_scan_window()?;
+        let video = VideoFile::open(filename, params)?;
+        let skip = params.skip_frames as f64 / video.fps;
+        let stride = params.sample_every as f64 / video.fps;
+        let seconds = if params.from_end {
+            let length = video.total_frames.map(|frames| frames as f64 / video.fps)
+                .ok_or_else(|| anyhow!("can't scan {} from the end, its length is unknown", filename.display()))?;
+            // a --scan-end past the end, or the very end itself, has no frame to decode
+            params.scan_end.unwrap_or(length - skip).min(length - stride).max(0.0)
+        } else {
+            params.scan_start.unwrap_or(skip)
+        };
+        Ok(SampledFrames {
+            stride,
+            seconds,
+            backward: params.from_end,
+            start: params.scan_start.unwrap_or(0.0),
+            end: params.scan_end,
+            samples: 0,
+            max_samples: params.max_samples,
+            video,
+        })
+    }
+
+    /// how many frames the video has, when the container says or it can be estimated
+    pub fn total_frames(&self) -> Option<u64> {
+        self.video.total_frames
+    }
+
+    // the next sampled frame still in the scaler's RGB frame, so callers can look at it before
+    // paying for a copy into an image
+    fn next_frame(&mut self) -> Result<Option<(Video, usize)>> {
+        let past_end = |seconds: f64| if self.backward { seconds < self.start } else { self.end.is_some_and(|end| seconds > end) };
+        if self.max_samples.is_some_and(|max| self.samples >= max) || past_end(self.seconds) {
+            return Ok(None);
+        }
+        let (rgb_frame, frame_seconds) = loop {
+            match self.video.frame_at(self.seconds)? {
+                Some(frame) => break frame,
+                // the length is only estimated for some containers, so going backwards the
+                // first sample can still be past the end
+                None if self.backward && self.samples == 0 && !past_end(self.seconds - self.stride) => {
+                    self.seconds -= self.stride;
+                }
+                None => return Ok(None),
+            }
+        };
+        if past_end(frame_seconds) {
+            return Ok(None);
+        }
+        self.samples += 1;
+        self.seconds = if self.backward {
+            frame_seconds.min(self.seconds) - self.stride
+        } else {
+            frame_seconds.max(self.seconds) + self.stride
+        };
+        Ok(Some((rgb_frame, (frame_seconds * self.video.fps).round() as usize)))
+    }
+}
+
+impl Iterator for SampledFrames {
+    type Item = Result<(RgbImage, usize)>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        match self.next_frame() {
+            Ok(Some((rgb_frame, frame_index))) => Some(to_image(&rgb_frame).map(|img| (img, frame_index))),
+            Ok(None) => None,
+            Err(e) => {
+                // stop after an error rather than retrying the same frame forever
+                self.max_samples = Some(0);
+                Some(Err(e))
+            }
+        }
+    }
+}
+
+/// the frame shown `seconds` into a video, whatever it looks like, and its frame index
+pub fn frame_at(filename: &Path, seconds: f64, params: &ExtractParams) -> Result<(RgbImage, usize)> {
+    let mut video = VideoFile::open(filename, params)?;
+    let (rgb_frame, frame_seconds) = video.frame_at(seconds)?
+        .ok_or_else(|| anyhow!("{} is shorter than {} seconds", filename.display(), seconds))?;
+    Ok((to_image(&rgb_frame)?, (frame_seconds * video.fps).round() as usize))
+}
+
+/// parse a timestamp given as SS, MM:SS or HH:MM:SS into seconds. the seconds may have a
+/// fractional part
+pub fn parse_timestamp(timestamp: &str) -> Result<f64> {
+    let parts = timestamp.split(':').collect::<Vec<_>>();
+    if parts.len() > 3 {
+        return Err(anyhow!("expected SS, MM:SS or HH:MM:SS, got {}", timestamp));
+    }
+    let mut seconds = 0.0;
+    for (i, part) in parts.iter().enumerate() {
+        let value = part.parse::<f64>().map_err(|_| anyhow!("expected SS, MM:SS or HH:MM:SS, got {}", timestamp))?;
+        if !value.is_finite() || value < 0.0 || (i > 0 && value >= 60.0) {
+            return Err(anyhow!("{} is out of range in timestamp {}", part, timestamp));
+        }
+        seconds = seconds * 60.0 + value;
+    }
+    Ok(seconds)
+}
+
+// how far past the last frame decoded the next one can be and still be reached by decoding on
+// instead of seeking. a seek lands on the keyframe before its target, so for a target closer
+// than the keyframe interval, which is rarely longer than this, it decodes frames again that
+// were already decoded
+const DECODE_AHEAD_SECONDS: f64 = 5.0;
+
+// an opened video stream with a decoder and a scaler to RGB
+struct VideoFile {
+    ictx: ffmpeg_next::format::context::Input,
+    decoder: ffmpeg_next::decoder::Video,
+    scaler: ffmpeg_next::software::scaling::context::Context,
+    index: usize,
+    fps: f64,
+    total_frames: Option<u64>,
+    // when the last frame decoded is shown, while the decoder is still just past it
+    decoded_to: Option<f64>,
+}
+
+impl VideoFile {
+    fn open(filename: &Path, params: &ExtractParams) -> Result<Self> {
+        let ictx = ffmpeg_next::format::input(filename)?;
+        let stream = ictx.streams().best(Type::Video).ok_or_else(|| VideoNamerError::NoVideoStream(filename.display().to_string()))?;
+        let index = stream.index();
+        let fps = Some(f64::from(stream.avg_frame_rate())).filter(|fps| fps.is_finite() && *fps > 0.0).unwrap_or(FPS as f64);
+        let total_frames = total_frames(&stream, ictx.duration());
+
+        let mut decoder_context = codec::context::Context::from_parameters(stream.parameters())?;
+        // a count of 0 leaves it to ffmpeg
+        decoder_context.set_threading(codec::threading::Config {
+            kind: codec::threading::Type::Frame,
+            ..codec::threading::Config::count(params.decode_threads.unwrap_or(0))
+        });
+        let decoder = decoder_context.decoder().video()?;
+
+        let scaler = ffmpeg_next::software::scaling::context::Context::get(
+            decoder.format(),
+            decoder.width(),
+            decoder.height(),
+            ffmpeg_next::format::Pixel::RGB24,
+            decoder.width(),
+            decoder.height(),
+            params.scaler.flags(),
+        )?;
+
+        Ok(VideoFile { ictx, decoder, scaler, index, fps, total_frames, decoded_to: None })
+    }
+
+    // decode the first frame at or after `seconds`, as RGB along with the time it's shown at,
+    // seeking unless it's a little ahead of the last frame decoded. None past the end of the video
+    fn frame_at(&mut self, seconds: f64) -> Result<Option<(Video, f64)>> {
+        let stream = self.stream()?;
+        let (time_base, start) = (stream.time_base(), start_time(&stream));
+        let timestamp = seconds_to_ts(seconds, time_base, start);
+        // seeking the whole input is done in AV_TIME_BASE units rather than the stream's
+        let seek_to = timestamp.rescale(time_base, ffmpeg_next::rescale::TIME_BASE);
+        if !self.decoded_to.is_some_and(|decoded_to| seconds > decoded_to && seconds - decoded_to <= DECODE_AHEAD_SECONDS) {
+            self.ictx.seek(seek_to, ..seek_to)?;
+            self.decoder.flush();
+        }
+
+        let Some(decoded) = decode_until(&mut self.ictx, &mut self.decoder, self.index, timestamp)? else {
+            // the decoder has been drained, so the next frame has to be sought
+            self.decoded_to = None;
+            return Ok(None);
+        };
+        let frame_seconds = match decoded.timestamp() {
+            Some(ts) => ts_to_seconds(ts, time_base, start),
+            None => seconds,
+        };
+        self.decoded_to = Some(frame_seconds);
+
+        let mut rgb_frame = Video::empty();
+        self.scaler.run(&decoded, &mut rgb_frame)?;
+        check_layout(&rgb_frame)?;
+        Ok(Some((rgb_frame, frame_seconds)))
+    }
+
+    fn stream(&self) -> Result<ffmpeg_next::Stream<'_>> {
+        self.ictx.stream(self.index).ok_or_else(|| anyhow!("the video stream went missing"))
+    }
+}
+
+// `seconds` into a stream as one of its timestamps: counted in its time base, and from its start
+// time, which isn't zero in containers like MPEG-TS. --at, --scan-start and --scan-end all seek
+// through this
+fn seconds_to_ts(seconds: f64, time_base: Rational, start: i64) -> i64 {
+    (seconds / f64::from(time_base)).round() as i64 + start
+}
+
+// how many seconds into a stream one of its timestamps is
+fn ts_to_seconds(ts: i64, time_base: Rational, start: i64) -> f64 {
+    (ts - start) as f64 * f64::from(time_base)
+}
+
+// when a stream starts in its time base, or 0 when the container doesn't say
+fn start_time(stream: &ffmpeg_next::Stream) -> i64 {
+    Some(stream.start_time()).filter(|start| *start != ffmpeg_next::ffi::AV_NOPTS_VALUE).unwrap_or(0)
+}
+
+// decode forward from wherever the input is to the first frame at or after `timestamp` (in the
+// stream's time base), or None at the end of the stream
+fn decode_until(ictx: &mut ffmpeg_next::format::context::Input, decoder: &mut ffmpeg_next::decoder::Video, index: usize, timestamp: i64) -> Result<Option<Video>> {
+    let mut decoded = Video::empty();
+    for (stream, packet) in ictx.packets() {
+        if stream.index() != index {
+            continue;
+        }
+        decoder.send_packet(&packet)?;
+        while decoder.receive_frame(&mut decoded).is_ok() {
+            if decoded.timestamp().is_none_or(|ts| ts >= timestamp) {
+                return Ok(Some(decoded));
+            }
+        }
+    }
+    decoder.send_eof()?;
+    while decoder.receive_frame(&mut decoded).is_ok() {
+        if decoded.timestamp().is_none_or(|ts| ts >= timestamp) {
+            return Ok(Some(decoded));
+        }
+    }
+    Ok(None)
+}
+
+// how many frames the stream has: from the stream itself when the container records it, otherwise
+// estimated from the stream's (or failing that the container's) duration and the frame rate
+fn total_frames(stream: &ffmpeg_next::Stream, container_duration: i64) -> Option<u64> {
+    if stream.frames() > 0 {
+        return Some(stream.frames() as u64);
+    }
+    let seconds = if stream.duration() > 0 {
+        stream.duration() as f64 * f64::from(stream.time_base())
+    } else {
+        container_duration as f64 * f64::from(ffmpeg_next::rescale::TIME_BASE)
+    };
+    let frames = seconds * f64::from(stream.avg_frame_rate());
+    (frames.is_finite() && frames > 0.0).then_some(frames as u64)
+}
+
+// the rows of an RGB24 frame's pixels, without the padding the scaler may add to the end of each
+// line to keep them aligned
+fn rows(frame: &Video) -> impl Iterator<Item = &[u8]> {
+    let width = frame.width() as usize * 3;
+    frame.data(0).chunks(frame.stride(0)).take(frame.height() as usize).map(move |row| &row[..width])
+}
+
+// rows() trusts that each line is at least width * 3 bytes and that there's a whole last row, so
+// make sure of it rather than panicking on a strange frame
+fn check_layout(frame: &Video) -> Result<()> {
+    let width = frame.width() as usize * 3;
+    let stride = frame.stride(0);
+    let needed = stride * (frame.height() as usize).saturating_sub(1) + width;
+    if stride < width || frame.data(0).len() < needed {
+        return Err(anyhow!("unexpected RGB frame layout: {}x{} with a stride of {} and {} bytes", frame.width(), frame.height(), stride, frame.data(0).len()));
+    }
+    Ok(())
+}
+
+// a tightly packed copy of the frame, row by row since lines may be padded past width * 3
+fn to_image(frame: &Video) -> Result<RgbImage> {
+    ImageBuffer::from_raw(frame.width(), frame.height(), rows(frame).flatten().copied().collect()).ok_or(anyhow!("couldn't decode image"))
+}
+
+// how much a frame looks like a title card: the share of the pixels in its color_sample_region,
+// or all of them, that are blue, to compare against min_ratio. only every pixel_stride'th pixel
+// is looked at
+fn blue_score(frame: &Video, params: &ExtractParams) -> f64 {
+    let (left, top, width, height) = match params.color_sample_region {
+        Some(region) => region.in_pixels(frame.width(), frame.height()),
+        None => (0, 0, frame.width(), frame.height()),
+    };
+    let (left, width) = (left as usize * 3, width as usize * 3);
+    let rows = rows(frame).skip(top as usize).take(height as usize).map(|row| &row[left..left + width]);
+    pixel_share(rows, params.pixel_stride, |r, g, b| is_blue(r, g, b, &params.thresholds))
+}
+
+/// the share of an image's pixels (0.0 to 1.0) that are blue by `thresholds`, the number a frame
+/// needs above min_ratio to count as a title card
+pub fn blue_share(frame: &RgbImage, thresholds: &ColorThresholds) -> f64 {
+    let rows = frame.as_raw().chunks(frame.width() as usize * 3);
+    pixel_share(rows, 1, |r, g, b| is_blue(r, g, b, thresholds))
+}
+
+/// whether more than min_ratio of an image is within color_tolerance of 78C8F0, the blue of
+/// Bluey's title cards, using the color detector's defaults. that isn't what extract_frames finds
+/// with its default rgb detector, which takes any blue with b > 220, r < 180 and g < 235: a pure
+/// 0000FF frame is a title card to extract_frames but not to this. use `blue_share` with the
+/// same `ColorThresholds` to agree with extract_frames
+pub fn is_blue_dominant(frame: &RgbImage) -> bool {
+    let defaults = ColorThresholds::default();
+    is_color_dominant(frame, defaults.target_color, defaults.color_tolerance, defaults.min_ratio)
+}
+
+/// whether more than `ratio` (0.0 to 1.0) of an image's pixels have every channel within
+/// `tolerance` of `target`
+pub fn is_color_dominant(frame: &RgbImage, target: Color, tolerance: u8, ratio: f64) -> bool {
+    let rows = frame.as_raw().chunks(frame.width() as usize * 3);
+    pixel_share(rows, 1, |r, g, b| is_near(r, g, b, target, tolerance)) > ratio
+}
+
+// the share of pixels in the rows that `matches` picks out, looking at every `stride`th one.
+// pixels are counted across row ends, so the ones looked at don't line up into columns
+fn pixel_share<'a>(rows: impl Iterator<Item = &'a [u8]>, stride: usize, matches: impl Fn(u8, u8, u8) -> bool) -> f64 {
+    let mut matching_pixels = 0;
+    let mut total_pixels = 0;
+    // how many pixels came before this row
+    let mut position = 0;
+
+    for row in rows {
+        let first = (stride - position % stride) % stride;
+        for pixel in row.chunks_exact(3).skip(first).step_by(stride) {
+            if matches(pixel[0], pixel[1], pixel[2]) {
+                matching_pixels += 1;
+            }
+            total_pixels += 1;
+        }
+        position += row.len() / 3;
+    }
+
+    matching_pixels as f64 / total_pixels as f64
+}
+
+fn is_near(r: u8, g: u8, b: u8, target: Color, tolerance: u8) -> bool {
+    let [tr, tg, tb] = target.0;
+    r.abs_diff(tr) <= tolerance && g.abs_diff(tg) <= tolerance && b.abs_diff(tb) <= tolerance
+}
+
+fn is_blue(r: u8, g: u8, b: u8, thresholds: &ColorThresholds) -> bool {
+    match thresholds.detector {
+        Detector::Rgb => b > thresholds.min_b && r < thresholds.max_r && g < thresholds.max_g, // Simple blue detection
+        Detector::Hsv => {
+            let (hue, saturation, value) = to_hsv(r, g, b);
+            (thresholds.min_hue..=thresholds.max_hue).contains(&hue)
+                && saturation >= thresholds.min_saturation
+                && value >= thresholds.min_value
+        }
+        Detector::Color => is_near(r, g, b, thresholds.target_color, thresholds.color_tolerance),
+    }
+}
+
+// hue in degrees (0.0 to 360.0), saturation and value (0.0 to 1.0) of an RGB pixel
+fn to_hsv(r: u8, g: u8, b: u8) -> (f64, f64, f64) {
+    let (r, g, b) = (r as f64 / 255.0, g as f64 / 255.0, b as f64 / 255.0);
+    let max = r.max(g).max(b);
+    let delta = max - r.min(g).min(b);
+    let hue = if delta == 0.0 {
+        0.0
+    } else if max == r {
+        60.0 * ((g - b) / delta).rem_euclid(6.0)
+    } else if max == g {
+        60.0 * ((b - r) / delta + 2.0)
+    } else {
+        60.0 * ((r - g) / delta + 4.0)
+    };
+    let saturation = if max == 0.0 { 0.0 } else { delta / max };
+    (hue, saturation, max)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // a frame whose first `first_pixels` pixels, row by row, are `first` and the rest are `rest`
+    fn split(width: u32, height: u32, first: [u8; 3], first_pixels: u32, rest: [u8; 3]) -> RgbImage {
+        RgbImage::from_fn(width, height, |x, y| image::Rgb(if y * width + x < first_pixels { first } else { rest }))
+    }
+
+    #[test]
+    fn dominant_color_isnt_only_blue() {
+        let red = split(10, 10, [220, 30, 40], 90, [0, 0, 0]);
+        assert!(is_color_dominant(&red, Color([230, 20, 30]), 20, 0.8));
+        assert!(!is_color_dominant(&red, Color([255, 255, 255]), 20, 0.8));
+        assert!(!is_blue_dominant(&red));
+
+        let white = split(10, 10, [250, 250, 245], 85, [30, 30, 120]);
+        assert!(is_color_dominant(&white, Color([255, 255, 255]), 15, 0.8));
+        assert!(!is_color_dominant(&white, Color([255, 255, 255]), 15, 0.9));
+        assert!(!is_blue_dominant(&white));
+
+        assert!(is_blue_dominant(&split(10, 10, [110, 190, 250], 90, [0, 0, 0])));
+        // the default rgb detector takes blues this doesn't
+        let pure_blue = split(10, 10, [0, 0, 255], 100, [0, 0, 0]);
+        assert!(!is_blue_dominant(&pure_blue));
+        assert!(blue_share(&pure_blue, &ColorThresholds::default()) > ColorThresholds::default().min_ratio);
+    }
+
+    #[test]
+    fn blue_share_is_the_share_of_blue_pixels() {
+        let thresholds = ColorThresholds::default();
+        let blue = [120, 200, 240];
+        // a frame needs more than min_ratio, so exactly 80% isn't enough
+        let share = blue_share(&split(10, 10, blue, 80, [0, 0, 0]), &thresholds);
+        assert_eq!(share, 0.8);
+        assert!(share <= thresholds.min_ratio);
+        let share = blue_share(&split(10, 10, blue, 81, [0, 0, 0]), &thresholds);
+        assert_eq!(share, 0.81);
+        assert!(share > thresholds.min_ratio);
+        assert_eq!(blue_share(&split(3, 1, blue, 0, [0, 0, 0]), &thresholds), 0.0);
+    }
+
+    #[test]
+    fn sampled_share_is_close_to_the_exact_one() {
+        let thresholds = ColorThresholds::default();
+        let is_blue = |r, g, b| is_blue(r, g, b, &thresholds);
+        // every fourth column blue, 37 pixels wide so rows don't start on a multiple of the stride
+        let stripes = RgbImage::from_fn(37, 23, |x, _| image::Rgb(if x % 4 == 0 { [120, 200, 240] } else { [0, 0, 0] }));
+        let rows = || stripes.as_raw().chunks(37 * 3);
+        let exact = pixel_share(rows(), 1, is_blue);
+        assert_eq!(exact, 10.0 / 37.0);
+        for stride in [2, 3, 4, 5] {
+            let sampled = pixel_share(rows(), stride, is_blue);
+            assert!((sampled - exact).abs() < 0.05, "stride {}: {} vs {}", stride, sampled, exact);
+        }
+    }
+
+    #[test]
+    fn timestamps_in_a_time_base() {
+        let millis = Rational::new(1, 1000);
+        assert_eq!(seconds_to_ts(1.5, millis, 0), 1500);
+        assert_eq!(ts_to_seconds(1500, millis, 0), 1.5);
+
+        // MPEG-TS: a 90kHz clock that starts 1.4 seconds in
+        let mpeg_ts = Rational::new(1, 90000);
+        assert_eq!(seconds_to_ts(10.0, mpeg_ts, 126000), 1026000);
+        assert_eq!(ts_to_seconds(1026000, mpeg_ts, 126000), 10.0);
+        assert_eq!(ts_to_seconds(126000, mpeg_ts, 126000), 0.0);
+
+        // NTSC film, where each frame lasts 1001/24000 of a second
+        let ntsc = Rational::new(1001, 24000);
+        assert_eq!(seconds_to_ts(1.001, ntsc, 0), 24);
+        for ts in 0..2000 {
+            assert_eq!(seconds_to_ts(ts_to_seconds(ts, ntsc, 0), ntsc, 0), ts);
+        }
+    }
+
+    #[test]
+    fn hsv_of_edge_cases() {
+        assert_eq!(to_hsv(0, 0, 255), (240.0, 1.0, 1.0));
+        assert_eq!(to_hsv(255, 0, 0), (0.0, 1.0, 1.0));
+        // red with a little blue is just under 360, not negative
+        let (hue, _, _) = to_hsv(255, 0, 10);
+        assert!((357.0..360.0).contains(&hue), "{}", hue);
+        // no delta between channels, so no hue or saturation
+        assert_eq!(to_hsv(128, 128, 128), (0.0, 0.0, 128.0 / 255.0));
+        assert_eq!(to_hsv(0, 0, 0), (0.0, 0.0, 0.0));
+    }
+
+    #[test]
+    fn detectors_agree_on_title_card_blue() {
+        let rgb = ColorThresholds::default();
+        let hsv = ColorThresholds { detector: Detector::Hsv, ..rgb };
+        // title card blue, a dimmer encode of it, light grey and red that wraps around the hue circle
+        let pixels = [[120, 200, 240], [80, 140, 200], [230, 230, 230], [255, 0, 10]];
+        let expected = [(true, true), (false, true), (false, false), (false, false)];
+        for ([r, g, b], (by_rgb, by_hsv)) in pixels.into_iter().zip(expected) {
+            assert_eq!(is_blue(r, g, b, &rgb), by_rgb, "rgb {:?}", [r, g, b]);
+            assert_eq!(is_blue(r, g, b, &hsv), by_hsv, "hsv {:?}", [r, g, b]);
+        }
+        let frame = RgbImage::from_fn(2, 2, |x, y| image::Rgb(pixels[(y * 2 + x) as usize]));
+        assert_eq!(blue_share(&frame, &rgb), 0.25);
+        assert_eq!(blue_share(&frame, &hsv), 0.5);
+    }
+}
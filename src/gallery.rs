@@ -0,0 +1,121 @@
+use anyhow::Result;
+use bluey::{get_closest_episodes, Episode, MatchParams};
+use ratatui::crossterm::event::{self, Event, KeyCode, KeyEventKind, KeyModifiers};
+use ratatui::layout::{Constraint, Layout};
+use ratatui::style::{Style, Stylize};
+use ratatui::text::Line;
+use ratatui::widgets::{Block, List, ListState, Paragraph};
+use ratatui::{DefaultTerminal, Frame};
+
+// how many episodes to offer for each file
+const CANDIDATES: usize = 10;
+
+// a file under review, what OCR read from it, the episode RenameAll suggested for it, and the
+// episode picked for it so far
+pub struct Item<'a> {
+    pub filename: String,
+    pub text: String,
+    pub suggestion: Option<(&'a Episode, f64)>,
+    pub decision: Option<(&'a Episode, f64)>,
+}
+
+// `closest` with `suggestion` moved or added to the front, at most `n` long
+pub fn suggested_first<'a>(suggestion: Option<(&'a Episode, f64)>, closest: Vec<(&'a Episode, f64)>, n: usize) -> Vec<(&'a Episode, f64)> {
+    let suggested = |episode: &Episode| suggestion.is_some_and(|(suggested, _)| std::ptr::eq(suggested, episode));
+    let mut offered = suggestion.into_iter().collect::<Vec<_>>();
+    offered.extend(closest.into_iter().filter(|(episode, _)| !suggested(episode)));
+    offered.truncate(n);
+    offered
+}
+
+// go through the files full screen, picking an episode for as many as wanted. true when the
+// picks should be applied, false when the gallery was quit with Ctrl-C
+pub fn choose<'a>(items: &mut [Item<'a>], episodes: &'a [Episode], matching: &MatchParams) -> Result<bool> {
+    let mut terminal = ratatui::init();
+    let result = run(&mut terminal, items, episodes, matching);
+    ratatui::restore();
+    result
+}
+
+fn run<'a>(terminal: &mut DefaultTerminal, items: &mut [Item<'a>], episodes: &'a [Episode], matching: &MatchParams) -> Result<bool> {
+    let mut current = 0;
+    // typing searches the episodes instead of offering the ones closest to the OCR text
+    let mut query = String::new();
+    let mut list = ListState::default().with_selected(Some(0));
+    loop {
+        let candidates = if query.is_empty() {
+            suggested_first(items[current].suggestion, get_closest_episodes(&items[current].text, episodes, matching, CANDIDATES), CANDIDATES)
+        } else {
+            get_closest_episodes(&query, episodes, matching, CANDIDATES)
+        };
+        terminal.draw(|frame| draw(frame, items, current, &query, &candidates, &mut list))?;
+
+        let Event::Key(key) = event::read()? else {
+            continue;
+        };
+        if key.kind != KeyEventKind::Press {
+            continue;
+        }
+        let mut go_to = None;
+        match key.code {
+            KeyCode::Char('c') if key.modifiers.contains(KeyModifiers::CONTROL) => return Ok(false),
+            KeyCode::Esc => return Ok(true),
+            KeyCode::Up => list.select_previous(),
+            KeyCode::Down => list.select_next(),
+            KeyCode::Enter => {
+                if let Some(&choice) = list.selected().and_then(|i| candidates.get(i)) {
+                    items[current].decision = Some(choice);
+                    go_to = Some(current + 1);
+                }
+            }
+            KeyCode::Delete => items[current].decision = None,
+            KeyCode::Right | KeyCode::Tab => go_to = Some(current + 1),
+            KeyCode::Left | KeyCode::BackTab => go_to = Some(current.saturating_sub(1)),
+            KeyCode::Backspace => {
+                query.pop();
+                list.select(Some(0));
+            }
+            KeyCode::Char(c) => {
+                query.push(c);
+                list.select(Some(0));
+            }
+            _ => {}
+        }
+        if let Some(next) = go_to.filter(|next| *next < items.len() && *next != current) {
+            current = next;
+            query.clear();
+            list.select(Some(0));
+        }
+    }
+}
+
+fn draw(frame: &mut Frame, items: &[Item], current: usize, query: &str, candidates: &[(&Episode, f64)], list: &mut ListState) {
+    let [header, body, footer] = Layout::vertical([Constraint::Length(4), Constraint::Min(0), Constraint::Length(1)]).areas(frame.area());
+
+    let item = &items[current];
+    let decided = items.iter().filter(|item| item.decision.is_some()).count();
+    let chosen = match item.decision {
+        Some((episode, distance)) => format!("{} {} (distance {})", episode.season_and_episode, episode.name, distance),
+        None => "nothing yet".to_string(),
+    };
+    frame.render_widget(Paragraph::new(vec![
+        Line::from(format!("{} of {} ({} picked): {}", current + 1, items.len(), decided, item.filename)).bold(),
+        Line::from(format!("OCR read: {}", item.text)),
+        Line::from(format!("picked: {}", chosen)),
+        Line::from(format!("search: {}", query)),
+    ]), header);
+
+    let suggested = |episode: &Episode| item.suggestion.is_some_and(|(suggested, _)| std::ptr::eq(suggested, episode));
+    let rows = candidates.iter().map(|(episode, distance)| {
+        let row = format!("{} {} (distance {})", episode.season_and_episode, episode.name, distance);
+        if suggested(episode) { format!("{}, suggested", row) } else { row }
+    }).collect::<Vec<_>>();
+    let title = if query.is_empty() { "suggested, then closest to what OCR read" } else { "closest to the search" };
+    let list_widget = List::new(rows)
+        .block(Block::bordered().title(title))
+        .highlight_style(Style::new().reversed())
+        .highlight_symbol("> ");
+    frame.render_stateful_widget(list_widget, body, list);
+
+    frame.render_widget(Line::from("up/down select, enter pick, left/right other files, del unpick, type to search, esc rename the picked files, ctrl-c quit").dim(), footer);
+}
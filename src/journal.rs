@@ -0,0 +1,87 @@
+use std::io::{BufRead, BufReader, Write};
+use std::path::{Path, PathBuf};
+use std::time::{SystemTime, UNIX_EPOCH};
+use anyhow::{anyhow, Result};
+use serde::de::DeserializeOwned;
+use serde::{Deserialize, Serialize};
+
+/// one rename, as recorded in the journal
+#[derive(Debug, Serialize, Deserialize)]
+pub struct JournalEntry {
+    pub from: PathBuf,
+    pub to: PathBuf,
+    /// seconds since the unix epoch
+    pub timestamp: u64,
+    pub distance: f64,
+    /// `to` is a link to `from` rather than where it was moved. missing from older journals
+    #[serde(default)]
+    pub linked: bool,
+}
+
+impl JournalEntry {
+    pub fn new(from: &Path, to: &Path, distance: f64) -> Result<Self> {
+        Ok(JournalEntry {
+            from: std::path::absolute(from)?,
+            to: std::path::absolute(to)?,
+            timestamp: SystemTime::now().duration_since(UNIX_EPOCH)?.as_secs(),
+            distance,
+            linked: false,
+        })
+    }
+}
+
+/// a file RenameAll wasn't confident enough to rename, kept for the Review subcommand
+#[derive(Debug, Serialize, Deserialize)]
+pub struct ReviewEntry {
+    pub file: PathBuf,
+    /// what OCR read off the title card
+    pub text: String,
+    /// the closest episode's season_and_episode
+    pub suggestion: String,
+    pub distance: f64,
+    /// where the title card was, for a {frame} in the template. missing from older logs
+    #[serde(default)]
+    pub frame_index: Option<usize>,
+}
+
+impl ReviewEntry {
+    pub fn new(file: &Path, text: &str, suggestion: &str, distance: f64, frame_index: Option<usize>) -> Result<Self> {
+        Ok(ReviewEntry {
+            file: std::path::absolute(file)?,
+            text: text.to_string(),
+            suggestion: suggestion.to_string(),
+            distance,
+            frame_index,
+        })
+    }
+}
+
+/// add an entry to the end of a JSON lines journal, creating it if needed
+pub fn append_journal<T: Serialize>(path: &Path, entry: &T) -> Result<()> {
+    let mut file = std::fs::OpenOptions::new().create(true).append(true).open(path)?;
+    writeln!(file, "{}", serde_json::to_string(entry)?)?;
+    Ok(())
+}
+
+/// replace a journal with just these entries
+pub fn write_journal<T: Serialize>(path: &Path, entries: &[T]) -> Result<()> {
+    let mut file = std::fs::File::create(path)?;
+    for entry in entries {
+        writeln!(file, "{}", serde_json::to_string(entry)?)?;
+    }
+    Ok(())
+}
+
+/// every entry in a journal, oldest first
+pub fn read_journal<T: DeserializeOwned>(path: &Path) -> Result<Vec<T>> {
+    let file = std::fs::File::open(path).map_err(|_| anyhow!("rename journal not found at {}", path.display()))?;
+    let mut entries = vec![];
+    for (i, line) in BufReader::new(file).lines().enumerate() {
+        let line = line?;
+        if line.trim().is_empty() {
+            continue;
+        }
+        entries.push(serde_json::from_str(&line).map_err(|e| anyhow!("line {} of {}: {}", i + 1, path.display(), e))?);
+    }
+    Ok(entries)
+}
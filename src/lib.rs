@@ -0,0 +1,224 @@
+//! find and read the title cards of a show about a blue dog, and match them up with episode names
+
+mod bktree;
+mod cache;
+mod episodes;
+mod error;
+mod fetch;
+mod frames;
+mod journal;
+mod ocr;
+mod show;
+mod subtitles;
+mod template;
+
+pub use cache::FrameCache;
+pub use episodes::{get_closest_episodes, get_corrected_episode_name, get_episode_names, load_overrides, normalize_title, parse_exclude, parse_season_episode, validate_episode_names, write_episode_names, CsvColumns, Distance, Episode, EpisodeIndex, MatchParams, Overrides};
+pub(crate) use episodes::{by_distance, deserialize_exclude};
+pub use error::VideoNamerError;
+pub use fetch::fetch_episodes;
+pub use frames::{blue_share, extract_frames, frame_at, is_blue_dominant, is_color_dominant, parse_timestamp, visit_title_cards, Color, ColorThresholds, Detector, ExtractParams, SampledFrames, Scaler};
+pub use journal::{append_journal, read_journal, write_journal, JournalEntry, ReviewEntry};
+pub use ocr::{get_episode_name, join_lines, LineFilter, ModelPaths, Ocr, Preprocess, Roi};
+pub use show::{load_show, Show};
+pub use subtitles::embedded_text;
+pub use template::Template;
+
+use std::path::Path;
+use std::sync::{LazyLock, Mutex};
+use std::time::{Duration, Instant};
+use anyhow::Result;
+use image::RgbImage;
+use indicatif::MultiProgress;
+use regex::Regex;
+use tracing::{debug, info, warn};
+
+/// where every progress bar and spinner is drawn, so a batch's overall bar and the bars for each
+/// file being worked on share the terminal without drawing over each other
+pub static PROGRESS: LazyLock<MultiProgress> = LazyLock::new(MultiProgress::new);
+
+/// find the first title card in a video, along with its frame index
+pub fn find_title_card(path: &Path, params: &ExtractParams) -> Result<(RgbImage, usize)> {
+    let params = ExtractParams { candidates: 1, ..*params };
+    let found = extract_frames(path, &params)?.into_iter().next();
+    Ok(found.ok_or_else(|| VideoNamerError::NoBlueFrame(path.display().to_string()))?)
+}
+
+/// read a title card and find the episode it names
+pub fn identify_episode<'a>(ocr: &Ocr, frame: &RgbImage, episodes: &EpisodeIndex<'a>, matching: &MatchParams) -> Result<&'a Episode> {
+    let (_, episode, _) = get_episode_name(ocr, frame, episodes, matching, false)?;
+    Ok(episode)
+}
+
+/// a title card frame along with what OCR read from it and the episode that best matches
+pub struct Candidate<'a> {
+    pub frame: RgbImage,
+    pub frame_index: usize,
+    pub name: String,
+    pub episode: &'a Episode,
+    pub distance: f64,
+}
+
+/// OCR each candidate frame and keep the one whose text is closest to a known episode. frames
+/// without any text, or whose text matches no episode, are skipped unless none of them had any.
+/// any other error is returned straight away
+pub fn best_candidate<'a>(ocr: &Ocr, candidates: Vec<(RgbImage, usize)>, episodes: &EpisodeIndex<'a>, matching: &MatchParams, interactive: bool) -> Result<Option<Candidate<'a>>> {
+    let mut best: Option<Candidate> = None;
+    let mut last_err = None;
+    for (frame, frame_index) in candidates {
+        let (name, episode, distance) = match get_episode_name(ocr, &frame, episodes, matching, interactive) {
+            Ok(reading) => reading,
+            Err(e) if matches!(e.downcast_ref(), Some(VideoNamerError::NoTextDetected | VideoNamerError::NoEpisodeFound)) => {
+                debug!(frame_index, "skipping candidate: {}", e);
+                last_err = Some(e);
+                continue;
+            }
+            Err(e) => return Err(e),
+        };
+        debug!(frame_index, name, distance, "candidate");
+        if best.as_ref().is_none_or(|best| distance < best.distance) {
+            best = Some(Candidate { frame, frame_index, name, episode, distance });
+        }
+    }
+    match (best, last_err) {
+        (None, Some(e)) => Err(e),
+        (best, _) => Ok(best),
+    }
+}
+
+/// what's needed to find a video's title card and match it to an episode, shared by RenameAll,
+/// Identify and Calibrate
+pub struct Identifier<'a> {
+    pub episodes: &'a EpisodeIndex<'a>,
+    pub extract: &'a ExtractParams,
+    pub matching: &'a MatchParams,
+    pub ocr: &'a Ocr,
+    pub use_existing_numbering: bool,
+    pub filename_hint: Option<&'a Regex>,
+    pub use_subtitles: bool,
+    pub cache: Option<&'a FrameCache>,
+    pub interactive: bool,
+    /// how long the files identified so far took, for the end of a batch
+    pub times: Mutex<StageTimes>,
+}
+
+/// how long identifying took at each stage, added up over files and the jobs working on them
+#[derive(Default, Clone, Copy)]
+pub struct StageTimes {
+    /// decoding and scanning for title cards, or loading them from --cache-dir
+    pub scan: Duration,
+    /// OCR and matching what it read
+    pub read: Duration,
+    /// --filename-hint and --use-subtitles
+    pub other: Duration,
+}
+
+/// a video's title card and the episode it names, confident or not. there's no frame when the
+/// episode came from --filename-hint or --use-subtitles alone
+pub struct Identified {
+    pub frame: Option<RgbImage>,
+    pub frame_index: Option<usize>,
+    /// what OCR read, or what --filename-hint or --use-subtitles found
+    pub name: String,
+    pub episode: Episode,
+    pub distance: f64,
+}
+
+impl StageTimes {
+    /// add another file's times to these
+    pub fn add(&mut self, other: StageTimes) {
+        self.scan += other.scan;
+        self.read += other.read;
+        self.other += other.other;
+    }
+}
+
+impl Identifier<'_> {
+    /// the best title card in a video and its episode, or the episode --filename-hint or
+    /// --use-subtitles finds when that's closer. None when there's none of them
+    pub fn identify(&self, file: &Path, filename: &str) -> Result<Option<Identified>> {
+        // only match against the episode the filename already numbers, when it numbers a known one
+        let numbered = match parse_season_episode(filename).filter(|_| self.use_existing_numbering) {
+            Some(numbering) => {
+                let numbered = self.episodes.episodes().iter().filter(|episode| parse_season_episode(&episode.season_and_episode) == Some(numbering)).cloned().collect::<Vec<_>>();
+                if numbered.is_empty() {
+                    warn!("{} is numbered S{:02}E{:02} but there's no such episode, matching against all of them", filename, numbering.0, numbering.1);
+                }
+                numbered
+            }
+            None => vec![],
+        };
+        let numbered_index;
+        let episodes = if numbered.is_empty() {
+            self.episodes
+        } else {
+            numbered_index = EpisodeIndex::new(&numbered, self.matching);
+            &numbered_index
+        };
+
+        let start = Instant::now();
+        let hinted = self.filename_hint
+            .and_then(|hint| hint.captures(filename)?.get(1))
+            .map(|text| text.as_str().replace(['.', '_'], " "))
+            .and_then(|text| {
+                let (episode, distance) = get_corrected_episode_name(&text, episodes, self.matching)?;
+                debug!(text, episode = episode.name, distance, "filename hint");
+                Some(Identified { frame: None, frame_index: None, name: text, episode: episode.clone(), distance })
+            });
+        let subtitled = if self.use_subtitles { self.subtitle_match(file, episodes) } else { None };
+        let other = start.elapsed();
+
+        let start = Instant::now();
+        let candidates = match self.cache {
+            Some(cache) => cache.extract_frames(file, self.extract)?,
+            None => extract_frames(file, self.extract)?,
+        };
+        let scan = start.elapsed();
+        let start = Instant::now();
+        let best = best_candidate(self.ocr, candidates, episodes, self.matching, self.interactive);
+        let times = StageTimes { scan, read: start.elapsed(), other };
+        debug!(file = %file.display(), scan = ?times.scan, read = ?times.read, other = ?times.other, "stage timings");
+        self.times.lock().unwrap().add(times);
+
+        let read = match best {
+            Ok(best) => best.map(|Candidate { frame, frame_index, name, episode, distance }| Identified {
+                frame: Some(frame),
+                frame_index: Some(frame_index),
+                name,
+                episode: episode.clone(),
+                distance,
+            }),
+            // the filename or subtitles can still say which episode it is when the title card can't
+            Err(e) if (hinted.is_some() || subtitled.is_some()) && matches!(e.downcast_ref(), Some(VideoNamerError::NoTextDetected | VideoNamerError::NoEpisodeFound)) => None,
+            Err(e) => return Err(e),
+        };
+        let mut best = read;
+        for (source, other) in [("the filename", hinted), ("the subtitles", subtitled)] {
+            let Some(other) = other else { continue };
+            if let Some(best) = &best {
+                if other.distance >= best.distance {
+                    continue;
+                }
+                info!(best.name, other.name, "{} is closer to an episode, using it", source);
+            }
+            best = Some(other);
+        }
+        Ok(best)
+    }
+
+    /// the closest episode to any of the text embedded in a video. a video whose text can't be
+    /// read still has its title card, so that's only warned about
+    fn subtitle_match(&self, file: &Path, episodes: &EpisodeIndex) -> Option<Identified> {
+        let texts = embedded_text(file).unwrap_or_else(|e| {
+            warn!("couldn't read subtitles from {}: {}", file.display(), e);
+            vec![]
+        });
+        texts.into_iter()
+            .filter_map(|text| {
+                let (episode, distance) = get_corrected_episode_name(&text, episodes, self.matching)?;
+                debug!(text, episode = episode.name, distance, "embedded text");
+                Some(Identified { frame: None, frame_index: None, name: text, episode: episode.clone(), distance })
+            })
+            .min_by(|a, b| a.distance.total_cmp(&b.distance))
+    }
+}
@@ -1,279 +1,1637 @@
-use serde::Deserialize;
+mod gallery;
+
+use std::fs::File;
+use std::io::{BufWriter, IsTerminal, Read, Write};
 use std::path::{Path, PathBuf};
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
 use clap::{Parser, Subcommand};
 use anyhow::{anyhow, Result};
+use bluey::{best_candidate, extract_frames, fetch_episodes, frame_at, get_closest_episodes, get_corrected_episode_name, get_episode_name, get_episode_names, load_show, parse_exclude, append_journal, parse_season_episode, parse_timestamp, read_journal, validate_episode_names, visit_title_cards, write_episode_names, write_journal, Candidate, Color, ColorThresholds, CsvColumns, Detector, Episode, EpisodeIndex, ExtractParams, FrameCache, Identified, Identifier, JournalEntry, LineFilter, MatchParams, ModelPaths, Ocr, Preprocess, ReviewEntry, Show, StageTimes, Template, VideoNamerError, PROGRESS};
 use clap_verbosity_flag::Verbosity;
-use ffmpeg_next::codec;
-use ffmpeg_next::media::Type;
-use ffmpeg_next::util::frame::video::Video;
 use glob::MatchOptions;
-use image::{ImageBuffer, RgbImage};
-use indicatif::ProgressBar;
-use inquire::Select;
-use ocrs::ImageSource;
+use image::codecs::jpeg::JpegEncoder;
+use image::{ImageFormat, RgbImage};
+use indicatif::{ProgressBar, ProgressStyle};
+use inquire::{Confirm, Select, Text};
+use rayon::prelude::*;
+use regex::Regex;
+use serde::Serialize;
 use tracing::{info, warn};
+use tracing_subscriber::layer::SubscriberExt;
+use tracing_subscriber::util::SubscriberInitExt;
 use tracing::debug;
 
-const FPS: usize = 24;
-
 /// a program that finds title cards for a show about a blue dog
 #[derive(Parser)]
+#[clap(after_help = "Exit codes: 0 success, 1 any other error, 2 no confident match (including no readable \
+text), 3 no title card found, 4 a model, video or other file couldn't be read, 64 a bad flag or argument, \
+130 interrupted")]
 struct Args {
+    // -v for more logging, and -q (--quiet) to turn off even errors so only results are printed.
+    // results go to stdout and logs to stderr either way
     #[clap(flatten)]
     verbose: Verbosity,
 
+    /// also append logs to this file, at the same verbosity
+    #[clap(long, global = true)]
+    log_file: Option<PathBuf>,
+
+    /// show profile to load from <NAME>.toml in $XDG_CONFIG_HOME/video_namer/shows or ./shows
+    /// (defaults to Bluey)
+    #[clap(long, global = true)]
+    show: Option<String>,
+
+    /// path to the CSV or JSON of episode names, gzipped or zipped if you like, or a directory of
+    /// per-season CSVs, overriding the show profile [default: bluey.csv]
+    #[clap(long, global = true)]
+    episodes: Option<String>,
+
+    /// CSV column with the episode names [default: name, or the show profile's columns.name]
+    #[clap(long, global = true)]
+    csv_name_col: Option<String>,
+
+    /// CSV column with the S01E03 style season and episode [default: season, or the show
+    /// profile's columns.season]
+    #[clap(long, global = true)]
+    csv_season_col: Option<String>,
+
+    /// never match episodes whose name matches this regex (ignoring case), like '^recap' for
+    /// entries that aren't real episodes. can be given more than once, and adds to the show
+    /// profile's exclude list
+    #[clap(long, global = true, value_parser = parse_exclude)]
+    exclude: Vec<Regex>,
+
+    #[clap(flatten)]
+    thresholds: ThresholdArgs,
+
+    #[clap(flatten)]
+    matching: MatchParams,
+
+    #[clap(flatten)]
+    models: ModelPaths,
+
+    #[clap(flatten)]
+    lines: LineFilter,
+
+    #[clap(flatten)]
+    preprocess: Preprocess,
+
+    /// keep the title card frames found in each video here and reuse them while the video's
+    /// path, size and modification time stay the same, so re-running with other OCR or matching
+    /// settings skips decoding. changing the detection settings finds the frames again
+    #[clap(long, global = true)]
+    cache_dir: Option<PathBuf>,
+
+    /// save every frame OCR reads to this directory as ocr-NNNN.png, with the words it found
+    /// outlined in green and the lines they were grouped into boxed in red, to see why the wrong
+    /// text was read. run with -vv to log which text each one was read as
+    #[clap(long, global = true)]
+    debug_ocr: Option<PathBuf>,
+
+    /// never prompt: when OCR finds several lines, use the one closest to an episode. this is
+    /// also what happens when stdin isn't a terminal, such as under cron or CI
+    #[clap(long, global = true)]
+    non_interactive: bool,
+
     #[clap(subcommand)]
     command: Commands,
 }
 
 #[derive(Subcommand)]
 enum Commands {
-    EpisodeName {
+    EpisodeName(EpisodeNameArgs),
+    Ocr(OcrArgs),
+    RenameAll(RenameArgs),
+    /// find and read the title card of each video and print the episode it is, without renaming
+    /// anything
+    Identify(IdentifyArgs),
+    /// identify videos that are already named correctly and check what's found against their
+    /// names, to see how well the current thresholds and matching do
+    Calibrate(CalibrateArgs),
+    /// save every sampled frame that looks like a title card, to see why detection does or
+    /// doesn't fire
+    DumpFrames {
         #[clap(short, long)]
         path: String,
 
+        /// directory to save the frames in, created if needed
         #[clap(short, long)]
-        output: String,
+        out_dir: PathBuf,
+
+        /// stop after this many frames
+        #[clap(long)]
+        max: Option<usize>,
+
+        #[clap(flatten)]
+        frames: FrameOutput,
+
+        #[clap(flatten)]
+        extract: ExtractParams,
     },
-    Ocr {
-        #[clap(short, long)]
-        path: String,
+    /// build an episode CSV from TMDB's episode list for a show
+    FetchEpisodes {
+        /// name of the show to search TMDB for
+        title: String,
+
+        /// TMDB API key
+        #[clap(long)]
+        api_key: String,
+
+        /// where to write the CSV
+        #[clap(long, default_value = "episodes.csv")]
+        out: String,
+
+        /// include specials (season 0), numbered S00Exx
+        #[clap(long)]
+        specials: bool,
+    },
+    /// check an episode CSV or JSON file, or a directory of CSVs, and report every problem with it
+    ValidateCsv {
+        /// file or directory to check [default: the show's episodes]
+        path: Option<String>,
     },
-    RenameAll {
-        pattern: String,
+    /// go through the files RenameAll wasn't confident about, choosing or typing the right
+    /// episode for each and renaming it. RenameAll only leaves files for this when it's run with
+    /// --max-distance
+    Review {
+        #[clap(default_value = REVIEW_LOG)]
+        log: String,
+
+        /// template for the new filename; {ext} is taken from the original file and {frame} is where
+        /// the title card was found
+        #[clap(long, default_value = TEMPLATE)]
+        template: Template,
+
+        /// file to record each rename in, so it can be undone
+        #[clap(long, default_value = JOURNAL)]
+        journal: String,
+
+        /// where the files go, which should be the same as for the RenameAll that left them
+        #[clap(flatten)]
+        destination: Destination,
+
+        /// review full screen instead of one prompt per file: see every file with its closest
+        /// episodes, pick or search for the right ones in any order, and rename them all at the end
+        #[clap(long)]
+        tui: bool,
+    },
+    /// put back the renames recorded in a rename journal, newest first
+    Undo {
+        #[clap(default_value = JOURNAL)]
+        log: String,
     },
 }
 
-fn main() -> Result<()> {
-    let args = Args::parse();
-    tracing_subscriber::fmt()
-        .with_max_level(args.verbose.tracing_level_filter())
+#[derive(clap::Args)]
+struct EpisodeNameArgs {
+    /// video to read, or an http(s):// URL to stream it from. over HTTP every sampled frame is a
+    /// seek, so the server needs to support range requests and scanning is slower than locally
+    #[clap(short, long)]
+    path: String,
+
+    #[clap(short, long)]
+    output: String,
+
+    #[clap(flatten)]
+    extract: ExtractParams,
+
+    /// save only the part of the frame with text in it, or the whole frame when there's none
+    #[clap(long)]
+    crop: bool,
+
+    /// read the title card at this time (SS, MM:SS or HH:MM:SS) instead of scanning for it
+    #[clap(long, value_parser = parse_timestamp)]
+    at: Option<f64>,
+
+    #[clap(flatten)]
+    frames: FrameOutput,
+
+    /// how to print the result
+    #[clap(long, value_enum, default_value_t = OutputFormat::Text)]
+    format: OutputFormat,
+}
+
+#[derive(clap::ValueEnum, Debug, Clone, Copy, PartialEq)]
+enum FrameFormat {
+    Png,
+    Jpeg,
+    Webp,
+}
+
+impl From<FrameFormat> for ImageFormat {
+    fn from(format: FrameFormat) -> Self {
+        match format {
+            FrameFormat::Png => ImageFormat::Png,
+            FrameFormat::Jpeg => ImageFormat::Jpeg,
+            FrameFormat::Webp => ImageFormat::WebP,
+        }
+    }
+}
+
+// how frames are saved
+#[derive(clap::Args)]
+struct FrameOutput {
+    /// format to save frames in [default: from the file's extension, or png when the name is
+    /// made up]. webp is lossless
+    #[clap(long, value_enum)]
+    frame_format: Option<FrameFormat>,
+
+    /// quality of jpeg frames, from 1 to 100
+    #[clap(long, default_value_t = 85, value_parser = clap::builder::RangedU64ValueParser::<u8>::new().range(1..=100))]
+    jpeg_quality: u8,
+}
+
+impl FrameOutput {
+    // an error up front when the image crate was built without an encoder for --frame-format
+    fn check(&self) -> Result<()> {
+        match self.frame_format.map(ImageFormat::from) {
+            Some(format) if !format.writing_enabled() => Err(anyhow!("this build can't write {:?} images", format)),
+            _ => Ok(()),
+        }
+    }
+
+    // the extension for frames whose names are made up rather than given
+    fn extension(&self) -> &'static str {
+        ImageFormat::from(self.frame_format.unwrap_or(FrameFormat::Png)).extensions_str()[0]
+    }
+
+    fn save(&self, frame: &RgbImage, path: &Path) -> Result<()> {
+        let format = match self.frame_format {
+            Some(format) => format.into(),
+            None => ImageFormat::from_path(path)?,
+        };
+        let mut out = BufWriter::new(File::create(path)?);
+        match format {
+            ImageFormat::Jpeg => frame.write_with_encoder(JpegEncoder::new_with_quality(&mut out, self.jpeg_quality))?,
+            format => frame.write_to(&mut out, format)?,
+        }
+        out.flush()?;
+        Ok(())
+    }
+}
+
+#[derive(clap::Args)]
+struct OcrArgs {
+    /// image to read, a glob of images, or an http(s):// URL to download one from
+    #[clap(short, long)]
+    path: String,
+
+    /// also list the N closest episodes with their distances
+    #[clap(long)]
+    top: Option<usize>,
+
+    /// how to print the result
+    #[clap(long, value_enum, default_value_t = OutputFormat::Text)]
+    format: OutputFormat,
+}
+
+#[derive(clap::Args)]
+struct IdentifyArgs {
+    #[clap(flatten)]
+    input: InputArgs,
+
+    #[clap(flatten)]
+    options: IdentifyOptions,
+
+    /// how to print the results
+    #[clap(long, value_enum, default_value_t = OutputFormat::Text)]
+    format: OutputFormat,
+}
+
+#[derive(clap::Args)]
+struct CalibrateArgs {
+    #[clap(flatten)]
+    input: InputArgs,
+
+    /// template the filenames were named with. files that don't match it are labeled by an S01E03
+    /// style number in their name instead, and skipped when they have neither
+    #[clap(long, default_value = TEMPLATE)]
+    template: Template,
+
+    #[clap(flatten)]
+    scan: ScanOptions,
+}
+
+// what RenameAll exits with after Ctrl-C, as shells do for SIGINT
+const INTERRUPTED_EXIT_CODE: i32 = 130;
+// the others, for scripts to tell "couldn't identify it" from "something broke"
+const NO_MATCH_EXIT_CODE: i32 = 2;
+const NO_TITLE_CARD_EXIT_CODE: i32 = 3;
+const UNREADABLE_EXIT_CODE: i32 = 4;
+// clap's own exit code for usage errors is 2, which scripts would take for "no confident match",
+// so they get sysexits' EX_USAGE instead
+const USAGE_EXIT_CODE: i32 = 64;
+
+const JOURNAL: &str = "video_namer_rename_log.jsonl";
+const REVIEW_LOG: &str = "video_namer_review.jsonl";
+const TEMPLATE: &str = "{show} - {season_episode} - {name}.{ext}";
+// held by a RenameAll while it renames, so two can't race over the same files
+const LOCK_FILE: &str = ".video_namer.lock";
+
+// which videos RenameAll and Identify work through
+#[derive(clap::Args)]
+#[clap(group(clap::ArgGroup::new("input").required(true).args(["pattern", "recursive"])))]
+struct InputArgs {
+    /// glob of video files
+    pattern: Option<String>,
+
+    /// every video under this directory instead of taking a glob
+    #[clap(long)]
+    recursive: Option<String>,
+
+    /// extensions that count as videos; other files are skipped without being decoded
+    #[clap(long, value_delimiter = ',', default_value = "mkv,mp4,avi,m4v,mov,webm,ts")]
+    ext: Vec<String>,
+
+    /// try to decode every file, whatever its extension, for unusual containers
+    #[clap(long)]
+    no_extension_filter: bool,
+}
+
+// how Identify and RenameAll find each video's episode: how Calibrate does, and also from what
+// its filename says, which Calibrate can't use since the filename is what it checks against
+#[derive(clap::Args)]
+struct IdentifyOptions {
+    /// when a filename already has an S01E03 style number, only match it against that episode
+    #[clap(long)]
+    use_existing_numbering: bool,
+
+    /// regex whose first capture group pulls an episode title out of the filename, like
+    /// 'S\d+E\d+\.(.+?)\.1080p'. it's matched alongside what OCR reads and whichever is closer to
+    /// an episode wins, so it also covers title cards that can't be read. dots and underscores in
+    /// the captured text count as spaces
+    #[clap(long, value_parser = parse_filename_hint)]
+    filename_hint: Option<Regex>,
+
+    #[clap(flatten)]
+    scan: ScanOptions,
+}
+
+// how Calibrate, Identify and RenameAll work through the videos and find their title cards
+#[derive(clap::Args)]
+struct ScanOptions {
+    /// also match the video's metadata title, chapter titles and first text subtitles against
+    /// the episodes, and use them when they're closer than the title card
+    #[clap(long)]
+    use_subtitles: bool,
+
+    /// number of files to process at once [default: number of CPUs]. with more than one, OCR
+    /// can't ask which line to use and takes the one closest to an episode instead. each file
+    /// is also decoded on several threads unless --decode-threads says otherwise
+    #[clap(short, long)]
+    jobs: Option<usize>,
+
+    #[clap(flatten)]
+    extract: ExtractParams,
+}
+
+#[derive(clap::Args)]
+struct RenameArgs {
+    #[clap(flatten)]
+    input: InputArgs,
+
+    /// run the whole pipeline but only log the renames instead of performing them
+    #[clap(long)]
+    dry_run: bool,
+
+    /// template for the new filename; {ext} is taken from the original file and {frame} is where
+    /// the title card was found
+    #[clap(long, default_value = TEMPLATE)]
+    template: Template,
+
+    #[clap(flatten)]
+    destination: Destination,
+
+    #[clap(flatten)]
+    options: IdentifyOptions,
+
+    /// ask before each rename
+    #[clap(long)]
+    confirm: bool,
+
+    /// process files even if their name already matches the template for a known episode
+    #[clap(long)]
+    force: bool,
+
+    /// file to record each rename in, so it can be undone
+    #[clap(long, default_value = JOURNAL)]
+    journal: String,
+
+    /// save the title card each rename was based on here, named after the new file, to check
+    /// matches afterwards
+    #[clap(long)]
+    save_frames: Option<PathBuf>,
+
+    #[clap(flatten)]
+    frames: FrameOutput,
+
+    /// file to record files without a confident match in, for the Review subcommand. only
+    /// --max-distance makes a match not confident, so without it nothing is recorded
+    #[clap(long, default_value = REVIEW_LOG)]
+    review_log: String,
+
+    /// only process the first N files found, to try settings out on part of a big batch
+    #[clap(long)]
+    limit: Option<usize>,
+}
+
+// where RenameAll and Review put renamed files and what they leave next to them
+#[derive(clap::Args)]
+struct Destination {
+    /// move renamed files into this library folder, in a "Season NN" folder for each season,
+    /// instead of leaving them where they are. a .video_namer.lock file here, or in the current
+    /// directory without this, stops a second RenameAll from starting while one is renaming
+    #[clap(long)]
+    output_dir: Option<PathBuf>,
+
+    /// what to do when a file with the new name already exists
+    #[clap(long, value_enum, default_value_t = OnConflict::Skip)]
+    on_conflict: OnConflict,
+
+    /// leave each video where it is and make a link to it with the new name instead of moving it,
+    /// to keep a download folder intact. a hard link that can't be made because the new name is
+    /// on another filesystem falls back to a symbolic link. Undo removes the links
+    #[clap(long, value_enum)]
+    link: Option<Link>,
+
+    /// write a Kodi/Jellyfin style .nfo next to each renamed video with the episode's title,
+    /// season and number. Undo leaves these behind
+    #[clap(long)]
+    write_nfo: bool,
+}
+
+#[derive(clap::ValueEnum, Debug, Clone, Copy, PartialEq)]
+enum OnConflict {
+    /// leave the file as it is
+    Skip,
+    /// replace the existing file
+    Overwrite,
+    /// add " (2)", " (3)" and so on before the extension until the name is free
+    Suffix,
+}
+
+#[derive(clap::ValueEnum, Debug, Clone, Copy, PartialEq)]
+enum Link {
+    /// another name for the same file, which has to be on the same filesystem
+    Hard,
+    /// a symbolic link to the original's absolute path
+    Soft,
+}
+
+fn main() {
+    if let Err(e) = run() {
+        eprintln!("Error: {:?}", e);
+        std::process::exit(exit_code(&e));
+    }
+}
+
+// the exit code for an error, from the first cause in its chain that's one of the kinds scripts
+// are told apart
+fn exit_code(e: &anyhow::Error) -> i32 {
+    e.chain().find_map(|cause| {
+        if let Some(e) = cause.downcast_ref::<VideoNamerError>() {
+            return match e {
+                VideoNamerError::NoTextDetected | VideoNamerError::NoEpisodeFound | VideoNamerError::NoConfidentMatch { .. } => Some(NO_MATCH_EXIT_CODE),
+                VideoNamerError::NoBlueFrame(_) => Some(NO_TITLE_CARD_EXIT_CODE),
+                VideoNamerError::ModelLoad { .. } | VideoNamerError::NoVideoStream(_) => Some(UNREADABLE_EXIT_CODE),
+                VideoNamerError::CsvParse { .. } => None,
+            };
+        }
+        (cause.is::<std::io::Error>() || cause.is::<ffmpeg_next::Error>() || cause.is::<image::ImageError>()).then_some(UNREADABLE_EXIT_CODE)
+    }).unwrap_or(1)
+}
+
+fn run() -> Result<()> {
+    let args = match Args::try_parse() {
+        Ok(args) => args,
+        // --help and --version come through here too, and aren't failures
+        Err(e) if !e.use_stderr() => {
+            e.print()?;
+            return Ok(());
+        }
+        Err(e) => {
+            e.print()?;
+            std::process::exit(USAGE_EXIT_CODE);
+        }
+    };
+    // logs go to stderr so stdout stays clean for --format json
+    let stderr_layer = tracing_subscriber::fmt::layer().with_writer(std::io::stderr);
+    let file_layer = match &args.log_file {
+        Some(path) => {
+            let file = std::fs::OpenOptions::new().create(true).append(true).open(path)
+                .map_err(|e| anyhow!("couldn't open log file {}: {}", path.display(), e))?;
+            Some(tracing_subscriber::fmt::layer().with_ansi(false).with_writer(Mutex::new(file)))
+        }
+        None => None,
+    };
+    tracing_subscriber::registry()
+        .with(stderr_layer)
+        .with(file_layer)
+        .with(args.verbose.tracing_level_filter())
         .init();
 
+    let mut show = match &args.show {
+        Some(name) => load_show(name)?,
+        None => Show::default(),
+    };
+    if let Some(episodes) = args.episodes {
+        show.episodes = episodes;
+    }
+    if let Some(name) = args.csv_name_col {
+        show.columns.name = name;
+    }
+    if let Some(season) = args.csv_season_col {
+        show.columns.season = season;
+    }
+    show.exclude.extend(args.exclude);
+    args.thresholds.apply(&mut show.thresholds);
+    let interactive = !args.non_interactive && std::io::stdin().is_terminal();
+    let cache = args.cache_dir.as_deref().map(FrameCache::open).transpose()?;
+
     match args.command {
-        Commands::EpisodeName { path, output } => episode_name(&path, &output),
-        Commands::Ocr { path } => ocr(&path),
-        Commands::RenameAll { pattern } => rename_all(&pattern),
+        Commands::EpisodeName(mut episode) => {
+            episode.extract.thresholds = show.thresholds;
+            episode.extract.check_scan_window()?;
+            episode.frames.check()?;
+            let ocr = Ocr::load(&args.models, args.lines, args.preprocess, args.debug_ocr.as_deref())?;
+            episode_name(&episode, &args.matching, &show, &ocr, cache.as_ref(), interactive)
+        }
+        Commands::Ocr(images) => {
+            let ocr = Ocr::load(&args.models, args.lines, args.preprocess, args.debug_ocr.as_deref())?;
+            ocr_images(&images, &args.matching, &show, &ocr, interactive)
+        }
+        Commands::RenameAll(mut rename) => {
+            rename.options.scan.extract.thresholds = show.thresholds;
+            rename.options.scan.extract.check_scan_window()?;
+            rename.frames.check()?;
+            let ocr = Ocr::load(&args.models, args.lines, args.preprocess, args.debug_ocr.as_deref())?;
+            rename_all(&rename, &args.matching, &show, &ocr, cache.as_ref(), interactive)
+        }
+        Commands::Identify(mut identify) => {
+            identify.options.scan.extract.thresholds = show.thresholds;
+            identify.options.scan.extract.check_scan_window()?;
+            let ocr = Ocr::load(&args.models, args.lines, args.preprocess, args.debug_ocr.as_deref())?;
+            identify_all(&identify, &args.matching, &show, &ocr, cache.as_ref(), interactive)
+        }
+        Commands::Calibrate(mut calibrate) => {
+            calibrate.scan.extract.thresholds = show.thresholds;
+            calibrate.scan.extract.check_scan_window()?;
+            let ocr = Ocr::load(&args.models, args.lines, args.preprocess, args.debug_ocr.as_deref())?;
+            calibrate_all(&calibrate, &args.matching, &show, &ocr, cache.as_ref())
+        }
+        Commands::DumpFrames { path, out_dir, max, frames, mut extract } => {
+            extract.thresholds = show.thresholds;
+            frames.check()?;
+            dump_frames(Path::new(&path), &out_dir, max, &frames, &extract)
+        }
+        Commands::FetchEpisodes { title, api_key, out, specials } => {
+            let episodes = fetch_episodes(&title, &api_key, specials)?;
+            write_episode_names(&out, &episodes)?;
+            info!("wrote {} episodes to {}", episodes.len(), out);
+            Ok(())
+        }
+        Commands::ValidateCsv { path } => validate_csv(path.as_deref().unwrap_or(&show.episodes), &show.columns),
+        Commands::Review { log, template, journal, destination, tui } => {
+            if !interactive {
+                return Err(anyhow!("Review has to prompt, so it can't be used with --non-interactive or without a terminal"));
+            }
+            if tui {
+                review_gallery(Path::new(&log), &template, &destination, Path::new(&journal), &args.matching, &show)
+            } else {
+                review(Path::new(&log), &template, &destination, Path::new(&journal), &args.matching, &show)
+            }
+        }
+        Commands::Undo { log } => undo(&log),
     }
 }
 
-fn rename_all(pattern: &str) -> Result<()> {
-    let episodes = get_episode_names("bluey.csv")?;
-    let mut files = glob::glob_with(pattern, MatchOptions {
-        case_sensitive: false,
-        require_literal_separator: false,
-        require_literal_leading_dot: true,
-    })?.map(|x| x.map_err(|x| anyhow!(x.to_string()))).collect::<Result<Vec<_>>>()?;
+#[derive(clap::ValueEnum, Debug, Clone, Copy, PartialEq)]
+enum OutputFormat {
+    /// log lines only
+    Text,
+    /// one JSON object per line on stdout
+    Json,
+}
+
+// what --format json prints
+#[derive(Serialize)]
+struct Identification<'a> {
+    path: &'a str,
+    raw_ocr_text: &'a str,
+    matched_name: &'a str,
+    matched_season_episode: &'a str,
+    distance: f64,
+    frame_index: Option<usize>,
+    // with --top, the closest episodes best first
+    #[serde(skip_serializing_if = "Option::is_none")]
+    top: Option<Vec<Ranked<'a>>>,
+}
+
+#[derive(Serialize)]
+struct Ranked<'a> {
+    name: &'a str,
+    season_episode: &'a str,
+    distance: f64,
+}
+
+impl Identification<'_> {
+    fn print(&self) -> Result<()> {
+        println!("{}", serde_json::to_string(self)?);
+        Ok(())
+    }
+}
+
+// command line overrides for the show profile's ColorThresholds
+#[derive(clap::Args, Debug)]
+struct ThresholdArgs {
+    /// how to decide whether a pixel is blue
+    #[clap(long, global = true, value_enum)]
+    detector: Option<Detector>,
+
+    /// a blue pixel's blue channel must be above this
+    #[clap(long, global = true)]
+    min_blue: Option<u8>,
+
+    /// a blue pixel's red channel must be below this
+    #[clap(long, global = true)]
+    max_red: Option<u8>,
+
+    /// a blue pixel's green channel must be below this
+    #[clap(long, global = true)]
+    max_green: Option<u8>,
+
+    /// with --detector hsv, the lowest hue in degrees that counts as blue
+    #[clap(long, global = true)]
+    min_hue: Option<f64>,
+
+    /// with --detector hsv, the highest hue in degrees that counts as blue
+    #[clap(long, global = true)]
+    max_hue: Option<f64>,
+
+    /// with --detector hsv, a blue pixel's saturation (0.0 to 1.0) must be at least this
+    #[clap(long, global = true)]
+    min_saturation: Option<f64>,
+
+    /// with --detector hsv, a blue pixel's value (0.0 to 1.0) must be at least this
+    #[clap(long, global = true)]
+    min_value: Option<f64>,
+
+    /// look for title cards mostly this colour (RRGGBB) instead of blue; implies --detector color
+    #[clap(long, global = true)]
+    target_color: Option<Color>,
+
+    /// with --detector color, how far each channel may be from --target-color
+    #[clap(long, global = true)]
+    color_tolerance: Option<u8>,
+
+    /// share of blue pixels (0.0 to 1.0) needed for a title card
+    #[clap(long, global = true)]
+    min_ratio: Option<f64>,
+}
+
+impl ThresholdArgs {
+    fn apply(&self, thresholds: &mut ColorThresholds) {
+        if let Some(target_color) = self.target_color {
+            thresholds.detector = Detector::Color;
+            thresholds.target_color = target_color;
+        }
+        if let Some(color_tolerance) = self.color_tolerance {
+            thresholds.color_tolerance = color_tolerance;
+        }
+        if let Some(detector) = self.detector {
+            thresholds.detector = detector;
+        }
+        if let Some(min_b) = self.min_blue {
+            thresholds.min_b = min_b;
+        }
+        if let Some(max_r) = self.max_red {
+            thresholds.max_r = max_r;
+        }
+        if let Some(max_g) = self.max_green {
+            thresholds.max_g = max_g;
+        }
+        if let Some(min_hue) = self.min_hue {
+            thresholds.min_hue = min_hue;
+        }
+        if let Some(max_hue) = self.max_hue {
+            thresholds.max_hue = max_hue;
+        }
+        if let Some(min_saturation) = self.min_saturation {
+            thresholds.min_saturation = min_saturation;
+        }
+        if let Some(min_value) = self.min_value {
+            thresholds.min_value = min_value;
+        }
+        if let Some(min_ratio) = self.min_ratio {
+            thresholds.min_ratio = min_ratio;
+        }
+    }
+}
+
+fn rename_all(rename: &RenameArgs, matching: &MatchParams, show: &Show, ocr: &Ocr, cache: Option<&FrameCache>, interactive: bool) -> Result<()> {
+    if rename.confirm && !interactive {
+        return Err(anyhow!("--confirm has to prompt, so it can't be used with --non-interactive or without a terminal"));
+    }
+    // a dry run renames nothing, so it can run alongside anything
+    let _lock = if rename.dry_run { None } else { Some(lock_renames(rename.destination.output_dir.as_deref().unwrap_or(Path::new(".")))?) };
+    let episodes = get_episode_names(&show.episodes, &show.columns, &show.exclude)?;
+    let index = EpisodeIndex::new(&episodes, matching);
+    let mut files = input_files(&rename.input)?;
+    if let Some(limit) = rename.limit.filter(|limit| *limit < files.len()) {
+        info!("processing the first {} of {} files", limit, files.len());
+        files.truncate(limit);
+    }
+
+    let pool = rayon::ThreadPoolBuilder::new().num_threads(rename.options.scan.jobs.unwrap_or(0)).build()?;
+    // prompts from several threads at once would trample each other
+    let interactive = interactive && pool.current_num_threads() == 1;
+    // files are decoded and read in parallel, but only one at a time gets to check its
+    // destination and rename
+    let rename_lock = Mutex::new(());
+    let identifier = Identifier {
+        episodes: &index,
+        extract: &rename.options.scan.extract,
+        matching,
+        ocr,
+        use_existing_numbering: rename.options.use_existing_numbering,
+        filename_hint: rename.options.filename_hint.as_ref(),
+        use_subtitles: rename.options.scan.use_subtitles,
+        cache,
+        interactive,
+        times: Mutex::default(),
+    };
+
+    // Ctrl-C lets the files being worked on finish or stop short of renaming, then prints the
+    // summary, so nothing is left renamed without being journaled. a second Ctrl-C quits at once
+    let interrupted = Arc::new(AtomicBool::new(false));
+    let handler_flag = interrupted.clone();
+    ctrlc::set_handler(move || {
+        if handler_flag.swap(true, Ordering::SeqCst) {
+            std::process::exit(INTERRUPTED_EXIT_CODE);
+        }
+        eprintln!("interrupted, finishing the files in progress (Ctrl-C again to quit now)");
+    })?;
 
+    let start = Instant::now();
     let len = files.len();
-    for (i, file) in files.iter_mut().enumerate() {
+    let process = |i: usize, file: &PathBuf| -> Result<Outcome> {
+        if interrupted.load(Ordering::SeqCst) {
+            return Ok(Outcome::Interrupted);
+        }
         info!("File {} of {}: {:?}", i, len, file);
 
         let filename = file.file_name().map(|x| x.to_string_lossy()).ok_or(anyhow!("file has no file_name"))?;
 
-        let blue_frame = extract_frames(file)?;
-        if let Some((frame, _)) = blue_frame {
-            debug!("found a blue frame");
-            let name = get_episode_name(&frame)?;
+        if !rename.force {
+            if let Some(episode) = rename.template.already_named(&filename, show, &episodes) {
+                info!(episode = episode.name, "already named, skipping {}", filename);
+                return Ok(Outcome::AlreadyNamed);
+            }
+        }
+
+        if let Some(Identified { frame, frame_index, name, episode: corrected, distance }) = identifier.identify(file, &filename)? {
             debug!(name, "episode name");
-            let corrected = get_corrected_episode_name(&name, &episodes).unwrap();
             debug!(corrected = corrected.name, "corrected episode name");
+            if !matching.is_confident(distance) {
+                warn!(name, corrected = corrected.name, distance, "no confident match for {}, skipping", filename);
+                if !rename.dry_run {
+                    let entry = ReviewEntry::new(file, &name, &corrected.season_and_episode, distance, frame_index)?;
+                    let _guard = rename_lock.lock().unwrap();
+                    append_journal(Path::new(&rename.review_log), &entry)?;
+                }
+                return Ok(Outcome::LowConfidence);
+            }
 
             info!("Correcting {} to {}", name, corrected.name);
 
-            let new_filename = format!("Bluey - {} - {}.mkv", corrected.season_and_episode, corrected.name);
-            info!("Renaming {} to {}", filename, new_filename);
-            let new_path = file.parent().unwrap().join(new_filename);
-            // check to see if there is already a destination file
-            if new_path.exists() {
-                warn!("Destination file already exists, skipping");
-                continue;
+            let new_path = rename.destination.path_for(file, &corrected, &rename.template, show, frame_index);
+            info!("Renaming {} to {}", filename, new_path.display());
+            let _guard = rename_lock.lock().unwrap();
+            if new_path == *file {
+                info!("{} is already named {}", filename, new_path.display());
+                return Ok(Outcome::AlreadyNamed);
             }
-            std::fs::rename(file, new_path)?;
+            let Some(new_path) = rename.destination.free_path(new_path) else {
+                return Ok(Outcome::Skipped);
+            };
+            if rename.dry_run {
+                info!("{} -> {}", file.display(), new_path.display());
+                return Ok(Outcome::Renamed);
+            }
+            if rename.confirm {
+                let message = format!("Rename {} -> {}? (distance {})", filename, new_path.display(), distance);
+                if !Confirm::new(&message).with_default(true).prompt()? {
+                    info!("Skipping {}", filename);
+                    return Ok(Outcome::Skipped);
+                }
+            }
+            if interrupted.load(Ordering::SeqCst) {
+                info!("interrupted, leaving {} as it is", filename);
+                return Ok(Outcome::Interrupted);
+            }
+            rename.destination.place(file, &new_path, &corrected, distance, Path::new(&rename.journal))?;
+            if let (Some(dir), Some(frame)) = (&rename.save_frames, frame) {
+                std::fs::create_dir_all(dir)?;
+                let stem = new_path.file_stem().map(|x| x.to_string_lossy()).unwrap_or_default();
+                rename.frames.save(&frame, &dir.join(format!("{}.{}", stem, rename.frames.extension())))?;
+            }
+            Ok(Outcome::Renamed)
         } else {
             warn!("no blue frame found for {}", filename);
+            Ok(Outcome::NoTitleCard)
+        }
+    };
+
+    // how far through the batch it is, above the bars for the files being worked on
+    let files_bar = PROGRESS.add(ProgressBar::new(len as u64).with_style(ProgressStyle::with_template("{pos}/{len} files {wide_bar} {elapsed} elapsed, {eta} left")?));
+    // one bad file shouldn't stop the rest of the batch
+    let results = pool.install(|| files.par_iter().enumerate().map(|(i, file)| {
+        let result = process(i, file);
+        files_bar.inc(1);
+        (file, result)
+    }).collect::<Vec<_>>());
+    files_bar.finish_and_clear();
+
+    let mut summary = Summary::default();
+    let mut failures = vec![];
+    for (file, result) in results {
+        match result {
+            Ok(outcome) => summary.add(outcome),
+            Err(e) => {
+                warn!("{}: {}", file.display(), e);
+                summary.errored += 1;
+                failures.push((file, e));
+            }
+        }
+    }
+    summary.print(start.elapsed(), *identifier.times.lock().unwrap(), rename.dry_run);
+
+    if !failures.is_empty() {
+        warn!("{} of {} files failed:", failures.len(), len);
+        for (file, e) in &failures {
+            warn!("  {}: {}", file.display(), e);
         }
     }
+    if interrupted.load(Ordering::SeqCst) {
+        std::process::exit(INTERRUPTED_EXIT_CODE);
+    }
+    if !failures.is_empty() {
+        return Err(anyhow!("{} of {} files failed", failures.len(), len));
+    }
     Ok(())
 }
 
-fn ocr(path: &str) -> Result<()> {
-    let image = image::open(path)?.into_rgb8();
-    let name = get_episode_name(&image)?;
-    info!(name, "episode name");
-    let episodes = get_episode_names("bluey.csv")?;
-    debug!(len = episodes.len(), "episodes loaded");
-    let lowest = episodes.iter().min_by_key(|episode| {
-        // TODO: other distances?
-        strsim::levenshtein(&episode.name, &name) as usize
-    }).ok_or(anyhow!("No episode found"))?;
+// a --filename-hint regex, which needs a group to capture the title with
+fn parse_filename_hint(hint: &str) -> Result<Regex> {
+    let regex = Regex::new(hint)?;
+    if regex.captures_len() < 2 {
+        return Err(anyhow!("needs a capture group around the title, like 'S\\d+E\\d+\\.(.+?)\\.1080p'"));
+    }
+    Ok(regex)
+}
+
+fn identify_all(identify: &IdentifyArgs, matching: &MatchParams, show: &Show, ocr: &Ocr, cache: Option<&FrameCache>, interactive: bool) -> Result<()> {
+    let episodes = get_episode_names(&show.episodes, &show.columns, &show.exclude)?;
+    let index = EpisodeIndex::new(&episodes, matching);
+    let files = input_files(&identify.input)?;
 
-    info!(lowest.name, lowest.season_and_episode, "closest episode");
+    let pool = rayon::ThreadPoolBuilder::new().num_threads(identify.options.scan.jobs.unwrap_or(0)).build()?;
+    let identifier = Identifier {
+        episodes: &index,
+        extract: &identify.options.scan.extract,
+        matching,
+        ocr,
+        use_existing_numbering: identify.options.use_existing_numbering,
+        filename_hint: identify.options.filename_hint.as_ref(),
+        use_subtitles: identify.options.scan.use_subtitles,
+        cache,
+        interactive: interactive && pool.current_num_threads() == 1,
+        times: Mutex::default(),
+    };
+    let results = pool.install(|| files.par_iter().map(|file| {
+        let filename = file.file_name().map(|x| x.to_string_lossy()).ok_or(anyhow!("file has no file_name"))?;
+        identifier.identify(file, &filename)
+    }).collect::<Vec<_>>());
+
+    // printed in order once everything's done, so output from parallel jobs doesn't interleave
+    let mut failed = 0;
+    for (file, result) in files.iter().zip(results) {
+        let path = file.display().to_string();
+        match (result, identify.format) {
+            (Ok(Some(found)), OutputFormat::Json) => Identification {
+                path: &path,
+                raw_ocr_text: &found.name,
+                matched_name: &found.episode.name,
+                matched_season_episode: &found.episode.season_and_episode,
+                distance: found.distance,
+                frame_index: found.frame_index,
+                top: None,
+            }.print()?,
+            (Ok(Some(found)), OutputFormat::Text) => {
+                let confidence = if matching.is_confident(found.distance) { "" } else { ", not confident" };
+                println!("{}: {} {} (distance {}{})", path, found.episode.season_and_episode, found.episode.name, found.distance, confidence);
+            }
+            (Ok(None), OutputFormat::Text) => println!("{}: no title card found", path),
+            (Ok(None), OutputFormat::Json) => warn!("no title card found in {}", path),
+            (Err(e), _) => {
+                warn!("{}: {}", path, e);
+                failed += 1;
+            }
+        }
+    }
+    if failed > 0 {
+        return Err(anyhow!("{} of {} files failed", failed, files.len()));
+    }
     Ok(())
 }
 
-fn episode_name(path: &str, output: &str) -> Result<()> {
-    let blue_frame = extract_frames(Path::new(path))?;
+// the episode a correctly named file is, from the template it was named with or else its S01E03
+// style number
+fn labeled_episode<'a>(filename: &str, template: &Template, show: &Show, episodes: &'a [Episode]) -> Option<&'a Episode> {
+    template.already_named(filename, show, episodes).or_else(|| {
+        let numbering = parse_season_episode(filename)?;
+        episodes.iter().find(|episode| parse_season_episode(&episode.season_and_episode) == Some(numbering))
+    })
+}
 
-    if let Some((frame, index)) = blue_frame {
-        info!(index, "found a blue frame");
-        // write frame to output
-        frame.save(output)?;
+fn calibrate_all(calibrate: &CalibrateArgs, matching: &MatchParams, show: &Show, ocr: &Ocr, cache: Option<&FrameCache>) -> Result<()> {
+    let episodes = get_episode_names(&show.episodes, &show.columns, &show.exclude)?;
+    let index = EpisodeIndex::new(&episodes, matching);
+    let files = input_files(&calibrate.input)?;
 
-        let name = get_episode_name(&frame)?;
-        info!(name, "episode name");
+    let labeled = files.iter().filter_map(|file| {
+        let filename = file.file_name()?.to_string_lossy();
+        match labeled_episode(&filename, &calibrate.template, show, &episodes) {
+            Some(expected) => Some((file, expected)),
+            None => {
+                warn!("can't tell which episode {} is from its name, skipping it", file.display());
+                None
+            }
+        }
+    }).collect::<Vec<_>>();
+    if labeled.is_empty() {
+        return Err(anyhow!("none of the files are named after an episode"));
+    }
 
-        let episodes = get_episode_names("bluey.csv")?;
-        debug!(len = episodes.len(), "episodes loaded");
-        let lowest = episodes.iter().min_by_key(|episode| {
-            // TODO: other distances?
-            strsim::normalized_levenshtein(&episode.name, &name) as usize
-        }).ok_or(anyhow!("No episode found"))?;
+    let pool = rayon::ThreadPoolBuilder::new().num_threads(calibrate.scan.jobs.unwrap_or(0)).build()?;
+    // the filename is the answer, so it mustn't help find it
+    let identifier = Identifier {
+        episodes: &index,
+        extract: &calibrate.scan.extract,
+        matching,
+        ocr,
+        use_existing_numbering: false,
+        filename_hint: None,
+        use_subtitles: calibrate.scan.use_subtitles,
+        cache,
+        interactive: false,
+        times: Mutex::default(),
+    };
+    let results = pool.install(|| labeled.par_iter().map(|(file, _)| identifier.identify(file, "")).collect::<Vec<_>>());
 
-        info!(lowest.name, lowest.season_and_episode, "closest episode");
-    } else {
-        info!("no blue frame found");
+    // confident is what RenameAll would have renamed, so precision is how often those were right
+    let (mut correct, mut confident, mut confident_correct) = (0, 0, 0);
+    let mut mismatches = vec![];
+    for ((file, expected), result) in labeled.iter().zip(results) {
+        let path = file.display();
+        match result {
+            Ok(Some(found)) => {
+                let right = found.episode.season_and_episode == expected.season_and_episode;
+                let sure = matching.is_confident(found.distance);
+                correct += right as usize;
+                confident += sure as usize;
+                confident_correct += (right && sure) as usize;
+                if !right {
+                    mismatches.push(format!("{}: expected {} {}, found {} {} from {:?} (distance {}{})",
+                        path, expected.season_and_episode, expected.name, found.episode.season_and_episode, found.episode.name,
+                        found.name, found.distance, if sure { "" } else { ", not confident" }));
+                }
+            }
+            Ok(None) => mismatches.push(format!("{}: expected {} {}, no title card found", path, expected.season_and_episode, expected.name)),
+            Err(e) => mismatches.push(format!("{}: expected {} {}, {}", path, expected.season_and_episode, expected.name, e)),
+        }
     }
 
+    for mismatch in &mismatches {
+        println!("{}", mismatch);
+    }
+    println!("accuracy: {}/{} ({:.1}%)", correct, labeled.len(), percent(correct, labeled.len()));
+    println!("precision: {}/{} confident matches right ({:.1}%)", confident_correct, confident, percent(confident_correct, confident));
     Ok(())
 }
 
-fn extract_frames(filename: &Path) -> Result<Option<(RgbImage, usize)>> {
-    let mut ictx = ffmpeg_next::format::input(filename)?;
-    let stream = ictx.streams().best(Type::Video).ok_or(anyhow!("Unable to decode"))?;
-    let index = stream.index();
-
-    let decoder_context = codec::context::Context::from_parameters(stream.parameters())?;
-    let mut decoder = decoder_context.decoder().video()?;
-
-    let mut scaler = ffmpeg_next::software::scaling::context::Context::get(
-        decoder.format(),
-        decoder.width(),
-        decoder.height(),
-        ffmpeg_next::format::Pixel::RGB24,
-        decoder.width(),
-        decoder.height(),
-        ffmpeg_next::software::scaling::flag::Flags::BILINEAR,
-    )?;
-
-
-    let mut frame_index = 0;
-    let bar = ProgressBar::new((7 * 60 * FPS) as u64);
-
-    let mut receive_and_process_decoded_frames =
-        |decoder: &mut ffmpeg_next::decoder::Video| -> Result<Option<(RgbImage, usize)>> {
-            let mut decoded = Video::empty();
-            while decoder.receive_frame(&mut decoded).is_ok() {
-                if frame_index > (28 * FPS) && frame_index % FPS == 0 {
-                    let mut rgb_frame = Video::empty();
-                    scaler.run(&decoded, &mut rgb_frame)?;
-                    if let Some(img) = is_blue_dominant(&rgb_frame)? {
-                        return Ok(Some((img, frame_index)));
-                    }
+fn percent(part: usize, whole: usize) -> f64 {
+    if whole == 0 { 0.0 } else { 100.0 * part as f64 / whole as f64 }
+}
+
+// how a file in RenameAll ended up, apart from failing
+enum Outcome {
+    Renamed,
+    AlreadyNamed,
+    NoTitleCard,
+    LowConfidence,
+    // the destination was taken or the rename wasn't confirmed
+    Skipped,
+    // Ctrl-C came before the file was renamed
+    Interrupted,
+}
+
+// how many files ended up each way over a RenameAll run
+#[derive(Default)]
+struct Summary {
+    renamed: usize,
+    already_named: usize,
+    no_title_card: usize,
+    low_confidence: usize,
+    skipped: usize,
+    interrupted: usize,
+    errored: usize,
+}
+
+impl Summary {
+    fn add(&mut self, outcome: Outcome) {
+        match outcome {
+            Outcome::Renamed => self.renamed += 1,
+            Outcome::AlreadyNamed => self.already_named += 1,
+            Outcome::NoTitleCard => self.no_title_card += 1,
+            Outcome::LowConfidence => self.low_confidence += 1,
+            Outcome::Skipped => self.skipped += 1,
+            Outcome::Interrupted => self.interrupted += 1,
+        }
+    }
+
+    fn print(&self, elapsed: Duration, times: StageTimes, dry_run: bool) {
+        println!("{}: {}", if dry_run { "would rename" } else { "renamed" }, self.renamed);
+        println!("already named: {}", self.already_named);
+        println!("skipped, no title card: {}", self.no_title_card);
+        println!("skipped, low confidence: {}", self.low_confidence);
+        println!("skipped, conflict or declined: {}", self.skipped);
+        if self.interrupted > 0 {
+            println!("not renamed, interrupted: {}", self.interrupted);
+        }
+        println!("errored: {}", self.errored);
+        println!("took {:.1}s", elapsed.as_secs_f64());
+        println!("finding title cards took {:.1}s, reading them {:.1}s, filenames and subtitles {:.1}s, added up over every job", times.scan.as_secs_f64(), times.read.as_secs_f64(), times.other.as_secs_f64());
+    }
+}
+
+// the files named by the glob, or found walking the --recursive directory, that look like videos
+fn input_files(input: &InputArgs) -> Result<Vec<PathBuf>> {
+    let files = match &input.recursive {
+        Some(dir) => {
+            let mut files = vec![];
+            for entry in walkdir::WalkDir::new(dir).sort_by_file_name() {
+                let entry = entry?;
+                if entry.file_type().is_file() {
+                    files.push(entry.into_path());
                 }
-                frame_index += 1;
-                bar.inc(1);
             }
-            Ok(None)
+            files
+        }
+        None => glob_files(input.pattern.as_deref().ok_or(anyhow!("either a pattern or --recursive is required"))?)?,
+    };
+    if input.no_extension_filter {
+        return Ok(files);
+    }
+
+    Ok(files.into_iter().filter(|file| {
+        let is_video = file.extension().is_some_and(|ext| input.ext.iter().any(|e| ext.eq_ignore_ascii_case(e)));
+        if !is_video {
+            debug!("{} doesn't look like a video, skipping", file.display());
+        }
+        is_video
+    }).collect())
+}
+
+fn glob_files(pattern: &str) -> Result<Vec<PathBuf>> {
+    glob::glob_with(pattern, MatchOptions {
+        case_sensitive: false,
+        require_literal_separator: false,
+        require_literal_leading_dot: true,
+    })?.map(|x| x.map_err(|x| anyhow!(x.to_string()))).collect()
+}
+
+fn dump_frames(path: &Path, out_dir: &Path, max: Option<usize>, frames: &FrameOutput, extract: &ExtractParams) -> Result<()> {
+    std::fs::create_dir_all(out_dir)?;
+    let mut count = 0;
+    visit_title_cards(path, extract, |frame, frame_index| {
+        count += 1;
+        let output = out_dir.join(format!("{:04}_frame_{}.{}", count, frame_index, frames.extension()));
+        info!(frame_index, "saving {}", output.display());
+        frames.save(&frame, &output)?;
+        Ok(max.is_none_or(|max| count < max))
+    })?;
+    info!("saved {} frames to {}", count, out_dir.display());
+    Ok(())
+}
+
+fn validate_csv(path: &str, columns: &CsvColumns) -> Result<()> {
+    let problems = validate_episode_names(path, columns)?;
+    for problem in &problems {
+        warn!("{}", problem);
+    }
+    if !problems.is_empty() {
+        return Err(anyhow!("{} problems found in {}", problems.len(), path));
+    }
+    info!("no problems found in {}", path);
+    Ok(())
+}
+
+// what can be done with a file under review
+enum Choice<'a> {
+    Episode(&'a Episode, f64),
+    Type,
+    Skip,
+}
+
+fn review(log: &Path, template: &Template, destination: &Destination, journal: &Path, matching: &MatchParams, show: &Show) -> Result<()> {
+    let entries: Vec<ReviewEntry> = read_journal(log)?;
+    let episodes = get_episode_names(&show.episodes, &show.columns, &show.exclude)?;
+    let index = EpisodeIndex::new(&episodes, matching);
+    // entries that were skipped stay for next time
+    let mut remaining = vec![];
+
+    let len = entries.len();
+    for (i, entry) in entries.into_iter().enumerate() {
+        if !entry.file.exists() {
+            warn!("{} no longer exists, dropping it", entry.file.display());
+            continue;
+        }
+        let filename = entry.file.file_name().map(|x| x.to_string_lossy().to_string()).unwrap_or_default();
+
+        let suggested = suggestion(&entry, &episodes);
+        let mut choices = gallery::suggested_first(suggested, get_closest_episodes(&entry.text, &episodes, matching, 5), 5).into_iter()
+            .map(|(episode, distance)| Choice::Episode(episode, distance))
+            .collect::<Vec<_>>();
+        choices.push(Choice::Type);
+        choices.push(Choice::Skip);
+        let labels = choices.iter().map(|choice| match choice {
+            Choice::Episode(episode, distance) if suggested.is_some_and(|(suggested, _)| std::ptr::eq(suggested, *episode)) => {
+                format!("{} {} (suggested, distance {})", episode.season_and_episode, episode.name, distance)
+            }
+            Choice::Episode(episode, distance) => format!("{} {} (distance {})", episode.season_and_episode, episode.name, distance),
+            Choice::Type => "type the episode name".to_string(),
+            Choice::Skip => "skip for now".to_string(),
+        }).collect();
+        let message = format!("{} of {}: {} reads \"{}\"", i + 1, len, filename, entry.text);
+        let choice = Select::new(&message, labels).raw_prompt()?;
+
+        let (episode, distance) = match choices[choice.index] {
+            Choice::Episode(episode, distance) => (episode, distance),
+            Choice::Type => {
+                let typed = Text::new("Episode name:").prompt()?;
+                let (episode, distance) = get_corrected_episode_name(&typed, &index, matching).ok_or(VideoNamerError::NoEpisodeFound)?;
+                info!("{} matched {} {}", typed, episode.season_and_episode, episode.name);
+                (episode, distance)
+            }
+            Choice::Skip => {
+                remaining.push(entry);
+                continue;
+            }
         };
 
-    for (stream, packet) in ictx.packets() {
-        if stream.index() == index {
-            decoder.send_packet(&packet)?;
-            if let Some(blue_frame) = receive_and_process_decoded_frames(&mut decoder)? {
-                return Ok(Some(blue_frame));
+        if !rename_reviewed(&entry, episode, distance, template, destination, journal, show)? {
+            remaining.push(entry);
+        }
+    }
+
+    write_journal(log, &remaining)?;
+    Ok(())
+}
+
+// the episode RenameAll suggested for a file and how far it was from what was read, when it's
+// still one of the episodes. it may have come from the filename, subtitles or --overrides rather
+// than the OCR text, so it isn't always among the closest to that
+fn suggestion<'a>(entry: &ReviewEntry, episodes: &'a [Episode]) -> Option<(&'a Episode, f64)> {
+    let episode = episodes.iter().find(|episode| episode.season_and_episode.eq_ignore_ascii_case(&entry.suggestion))?;
+    Some((episode, entry.distance))
+}
+
+// Review with --tui: every choice is made first in the gallery, then the picked files are renamed
+fn review_gallery(log: &Path, template: &Template, destination: &Destination, journal: &Path, matching: &MatchParams, show: &Show) -> Result<()> {
+    let mut entries: Vec<ReviewEntry> = read_journal(log)?;
+    let episodes = get_episode_names(&show.episodes, &show.columns, &show.exclude)?;
+    entries.retain(|entry| {
+        let exists = entry.file.exists();
+        if !exists {
+            warn!("{} no longer exists, dropping it", entry.file.display());
+        }
+        exists
+    });
+    if entries.is_empty() {
+        info!("nothing to review");
+        return write_journal(log, &entries);
+    }
+
+    let mut items = entries.iter().map(|entry| gallery::Item {
+        filename: entry.file.file_name().map(|x| x.to_string_lossy().to_string()).unwrap_or_default(),
+        text: entry.text.clone(),
+        suggestion: suggestion(entry, &episodes),
+        decision: None,
+    }).collect::<Vec<_>>();
+    if !gallery::choose(&mut items, &episodes, matching)? {
+        info!("quit without renaming anything");
+        return Ok(());
+    }
+
+    let mut remaining = vec![];
+    for (entry, item) in entries.into_iter().zip(items) {
+        let renamed = match item.decision {
+            Some((episode, distance)) => rename_reviewed(&entry, episode, distance, template, destination, journal, show)?,
+            None => false,
+        };
+        if !renamed {
+            remaining.push(entry);
+        }
+    }
+    write_journal(log, &remaining)
+}
+
+// rename a reviewed file to the episode picked for it, unless --on-conflict skips it because the
+// name is taken. whether it's done with
+fn rename_reviewed(entry: &ReviewEntry, episode: &Episode, distance: f64, template: &Template, destination: &Destination, journal: &Path, show: &Show) -> Result<bool> {
+    let new_path = destination.path_for(&entry.file, episode, template, show, entry.frame_index);
+    if new_path == entry.file {
+        info!("{} is already named for {}", entry.file.display(), episode.name);
+        return Ok(true);
+    }
+    let Some(new_path) = destination.free_path(new_path) else {
+        info!("keeping {} for later", entry.file.display());
+        return Ok(false);
+    };
+    info!("Renaming {} to {}", entry.file.display(), new_path.display());
+    destination.place(&entry.file, &new_path, episode, distance, journal)?;
+    Ok(true)
+}
+
+impl Destination {
+    // the folder a file identified as `episode` goes in: its season's folder under --output-dir,
+    // or the one it's already in
+    fn dir_for(&self, file: &Path, episode: &Episode) -> PathBuf {
+        match &self.output_dir {
+            Some(dir) => match episode.season() {
+                Some(season) => dir.join(format!("Season {:02}", season)),
+                None => dir.clone(),
+            },
+            None => file.parent().unwrap_or(Path::new(".")).to_path_buf(),
+        }
+    }
+
+    // where a file identified as `episode` is renamed to, keeping its extension, before checking
+    // whether that's taken
+    fn path_for(&self, file: &Path, episode: &Episode, template: &Template, show: &Show, frame_index: Option<usize>) -> PathBuf {
+        self.dir_for(file, episode).join(template.render(show, episode, &file_extension(file), frame_index))
+    }
+
+    // the path to rename to when `path` may already be taken: itself, or a free name with
+    // --on-conflict suffix. None when --on-conflict says to skip the file
+    fn free_path(&self, path: PathBuf) -> Option<PathBuf> {
+        if !path.exists() {
+            return Some(path);
+        }
+        match self.on_conflict {
+            OnConflict::Skip => {
+                warn!("Destination file {} already exists, skipping", path.display());
+                None
+            }
+            OnConflict::Overwrite => {
+                warn!("Destination file {} already exists, overwriting it", path.display());
+                Some(path)
+            }
+            OnConflict::Suffix => {
+                let path = with_free_suffix(&path);
+                warn!("Destination file already exists, using {} instead", path.display());
+                Some(path)
             }
         }
     }
-    decoder.send_eof()?;
-    if let Some(blue_frame) = receive_and_process_decoded_frames(&mut decoder)? {
-        return Ok(Some(blue_frame));
+
+    // move or link `file` to `new_path`, record it in the journal and write its .nfo
+    fn place(&self, file: &Path, new_path: &Path, episode: &Episode, distance: f64, journal: &Path) -> Result<()> {
+        if let Some(dir) = new_path.parent() {
+            std::fs::create_dir_all(dir)?;
+        }
+        let entry = match self.link {
+            Some(link) => {
+                link_file(file, new_path, link)?;
+                JournalEntry { linked: true, ..JournalEntry::new(file, new_path, distance)? }
+            }
+            None => {
+                let entry = JournalEntry::new(file, new_path, distance)?;
+                move_file(file, new_path)?;
+                entry
+            }
+        };
+        append_journal(journal, &entry)?;
+        if self.write_nfo {
+            write_nfo(&new_path.with_extension("nfo"), episode)?;
+        }
+        Ok(())
     }
+}
 
-    Ok(None)
+fn undo(log: &str) -> Result<()> {
+    let entries: Vec<JournalEntry> = read_journal(Path::new(log))?;
+    for entry in entries.iter().rev() {
+        if !entry.to.exists() {
+            warn!("{} no longer exists, skipping", entry.to.display());
+            continue;
+        }
+        if entry.linked {
+            // a hard link may be all that's left of a video whose original has gone
+            if !entry.from.exists() {
+                warn!("{} no longer exists, keeping the link to it at {}", entry.from.display(), entry.to.display());
+                continue;
+            }
+            info!("Removing the link {} to {}", entry.to.display(), entry.from.display());
+            std::fs::remove_file(&entry.to)?;
+            continue;
+        }
+        if entry.from.exists() {
+            warn!("{} already exists, not overwriting it with {}", entry.from.display(), entry.to.display());
+            continue;
+        }
+        info!("Renaming {} back to {}", entry.to.display(), entry.from.display());
+        move_file(&entry.to, &entry.from)?;
+    }
+    Ok(())
 }
 
-// Check if the frame is mostly blue
-fn is_blue_dominant(frame: &Video) -> Result<Option<RgbImage>> {
-    let width = frame.width();
-    let height = frame.height();
-    let data = frame.data(0);
+// take the lock file in `dir`, which is held until the returned file is closed, even if the
+// process dies. the file itself is left behind, since only the lock on it matters
+fn lock_renames(dir: &Path) -> Result<File> {
+    std::fs::create_dir_all(dir)?;
+    let path = dir.join(LOCK_FILE);
+    let file = std::fs::OpenOptions::new().create(true).truncate(false).write(true).open(&path)
+        .map_err(|e| anyhow!("couldn't open lock file {}: {}", path.display(), e))?;
+    match file.try_lock() {
+        Ok(()) => Ok(file),
+        Err(std::fs::TryLockError::WouldBlock) => Err(anyhow!("another RenameAll is already renaming in {} (it holds {}), wait for it to finish", dir.display(), path.display())),
+        Err(std::fs::TryLockError::Error(e)) => Err(anyhow!("couldn't lock {}: {}", path.display(), e)),
+    }
+}
 
-    let img: RgbImage = ImageBuffer::from_raw(width, height, data.to_vec()).ok_or(anyhow!("couldn't decode image"))?;
+// the first of "name (2).ext", "name (3).ext" and so on that doesn't exist yet
+fn with_free_suffix(path: &Path) -> PathBuf {
+    let stem = path.file_stem().map(|x| x.to_string_lossy()).unwrap_or_default();
+    let ext = path.extension().map(|x| format!(".{}", x.to_string_lossy())).unwrap_or_default();
+    (2..)
+        .map(|n| path.with_file_name(format!("{} ({}){}", stem, n, ext)))
+        .find(|candidate| !candidate.exists())
+        .unwrap()
+}
 
-    let mut blue_pixels = 0;
-    let mut total_pixels = 0;
+// rename a file, or copy it and delete the original when it's going to another filesystem
+fn move_file(from: &Path, to: &Path) -> Result<()> {
+    match std::fs::rename(from, to) {
+        Err(e) if e.kind() == std::io::ErrorKind::CrossesDevices => {
+            if let Err(e) = std::fs::copy(from, to) {
+                // don't leave half a copy behind
+                let _ = std::fs::remove_file(to);
+                return Err(e.into());
+            }
+            std::fs::remove_file(from)?;
+            Ok(())
+        }
+        result => Ok(result?),
+    }
+}
 
-    for pixel in img.pixels() {
-        let [r, g, b] = pixel.0;
-        if b > 220 && r < 180 && g < 235 { // Simple blue detection
-            blue_pixels += 1;
+// make `to` a link to `from`, replacing anything already there since --on-conflict has allowed it
+fn link_file(from: &Path, to: &Path, link: Link) -> Result<()> {
+    if to.symlink_metadata().is_ok() {
+        std::fs::remove_file(to)?;
+    }
+    if link == Link::Hard {
+        match std::fs::hard_link(from, to) {
+            Err(e) if e.kind() == std::io::ErrorKind::CrossesDevices => {
+                warn!("{} is on another filesystem from {}, making a symbolic link instead", to.display(), from.display());
+            }
+            result => return Ok(result?),
         }
-        total_pixels += 1;
     }
+    let target = std::path::absolute(from)?;
+    #[cfg(unix)]
+    std::os::unix::fs::symlink(&target, to)?;
+    #[cfg(windows)]
+    std::os::windows::fs::symlink_file(&target, to)?;
+    Ok(())
+}
 
-    if (blue_pixels as f64 / total_pixels as f64) > 0.8 {
-        return Ok(Some(img));
+// a minimal episode NFO for media servers. the season and episode are left out when
+// season_and_episode isn't S01E03 style
+fn write_nfo(path: &Path, episode: &Episode) -> Result<()> {
+    let mut nfo = String::from("<?xml version=\"1.0\" encoding=\"UTF-8\" standalone=\"yes\"?>\n<episodedetails>\n");
+    nfo.push_str(&format!("  <title>{}</title>\n", xml_escape(&episode.name)));
+    if let Some((season, number)) = parse_season_episode(&episode.season_and_episode) {
+        nfo.push_str(&format!("  <season>{}</season>\n  <episode>{}</episode>\n", season, number));
     }
+    nfo.push_str("</episodedetails>\n");
+    std::fs::write(path, nfo).map_err(|e| anyhow!("couldn't write {}: {}", path.display(), e))
+}
 
-    Ok(None)
+fn xml_escape(text: &str) -> String {
+    text.replace('&', "&amp;").replace('<', "&lt;").replace('>', "&gt;").replace('"', "&quot;").replace('\'', "&apos;")
 }
 
-fn file_path(path: &str) -> PathBuf {
-    let mut abs_path = PathBuf::from(env!("CARGO_MANIFEST_DIR"));
-    abs_path.push(path);
-    abs_path
+// the original file's extension, so an .mp4 stays an .mp4
+fn file_extension(path: &Path) -> String {
+    match path.extension().map(|x| x.to_string_lossy()) {
+        Some(ext) if !ext.is_empty() => ext.to_string(),
+        _ => "mkv".to_string(),
+    }
 }
 
-fn get_corrected_episode_name(candiate_name: &str, episodes: &[Episode]) -> Option<Episode> {
-    episodes.iter().min_by_key(|episode| {
-        strsim::levenshtein(&episode.name, candiate_name) as usize
-    }).cloned()
+fn ocr_images(args: &OcrArgs, matching: &MatchParams, show: &Show, ocr: &Ocr, interactive: bool) -> Result<()> {
+    let episodes = get_episode_names(&show.episodes, &show.columns, &show.exclude)?;
+    debug!(len = episodes.len(), "episodes loaded");
+    let index = EpisodeIndex::new(&episodes, matching);
+    // in text mode the match is all that goes to stdout, next to its file when there's more than one
+    let print = |path: &str, episode: &Episode, many: bool| {
+        if let (OutputFormat::Text, None) = (args.format, args.top) {
+            if many {
+                println!("{}: {}", path, episode.name);
+            } else {
+                println!("{}", episode.name);
+            }
+        }
+    };
+    if is_url(&args.path) {
+        let matched = ocr_image(&args.path, &download_image(&args.path)?, args, ocr, &index, matching, interactive)?;
+        print(&args.path, matched, false);
+        return Ok(());
+    }
+
+    let files = glob_files(&args.path)?;
+    if files.is_empty() {
+        return Err(anyhow!("no images match {}", args.path));
+    }
+    // the first image without a confident match, and how many there were, to fail with at the end
+    let mut unmatched = None;
+    let mut unmatched_count = 0;
+    for file in &files {
+        // with a glob, one unreadable image shouldn't stop the rest
+        let path = file.display().to_string();
+        let result = image::open(file).map_err(anyhow::Error::from)
+            .and_then(|image| ocr_image(&path, &image.into_rgb8(), args, ocr, &index, matching, interactive));
+        match result {
+            Ok(matched) => print(&path, matched, files.len() > 1),
+            Err(e) if files.len() == 1 => return Err(e),
+            Err(e) if exit_code(&e) == NO_MATCH_EXIT_CODE => {
+                warn!("{}: {}", path, e);
+                unmatched_count += 1;
+                unmatched.get_or_insert(e.context(path));
+            }
+            Err(e) => warn!("{}: {}", path, e),
+        }
+    }
+    match unmatched {
+        Some(e) => Err(e.context(format!("{} of {} images had no confident match", unmatched_count, files.len()))),
+        None => Ok(()),
+    }
 }
 
-fn get_episode_name(frame: &RgbImage) -> Result<String> {
-    let detection_model_path = file_path("text-detection.rten");
-    let rec_model_path = file_path("text-recognition.rten");
+// the biggest image --path will download, well past any real frame
+const MAX_DOWNLOAD: u64 = 64 * 1024 * 1024;
 
-    let detection_model = rten::Model::load_file(detection_model_path)?;
-    let recognition_model = rten::Model::load_file(rec_model_path)?;
+fn is_url(path: &str) -> bool {
+    path.starts_with("http://") || path.starts_with("https://")
+}
 
-    let engine = ocrs::OcrEngine::new(ocrs::OcrEngineParams {
-        detection_model: Some(detection_model),
-        recognition_model: Some(recognition_model),
-        ..Default::default()
+fn download_image(url: &str) -> Result<RgbImage> {
+    let response = ureq::get(url).call().map_err(|e| match e {
+        ureq::Error::Status(code, response) => anyhow!("{} returned {} {}", url, code, response.status_text()),
+        ureq::Error::Transport(e) => anyhow!("couldn't download {}: {}", url, e),
     })?;
+    let mut bytes = vec![];
+    response.into_reader().take(MAX_DOWNLOAD).read_to_end(&mut bytes)?;
+    Ok(image::load_from_memory(&bytes).map_err(|e| anyhow!("{} isn't an image: {}", url, e))?.into_rgb8())
+}
 
-    let img_source = ImageSource::from_bytes(frame.as_raw(), frame.dimensions())?;
-    let ocr_input = engine.prepare_input(img_source)?;
-
-    let word_rects = engine.detect_words(&ocr_input)?;
-    debug!(len = word_rects.len(), "detected words");
-    let line_rects = engine.find_text_lines(&ocr_input, &word_rects);
-    debug!(len = line_rects.len(), "detected lines");
-    let line_texts = engine.recognize_text(&ocr_input, &line_rects)?;
-
-    let lines = line_texts.iter().flatten().map(|x| x.to_string()).filter(|x| x.len() > 1).collect::<Vec<_>>();
-    debug!("{:#?}", lines);
-    match &lines[..] {
-        [] => Err(anyhow!("No text detected")),
-        [text] => Ok(text.to_string()),
-        options => {
-            Ok(Select::new("Choose an OCR option:", options.to_vec())
-                .prompt()?.to_string())
-        },
+// the episode an image names. a match that isn't confident is a NoConfidentMatch error
+fn ocr_image<'a>(path: &str, image: &RgbImage, args: &OcrArgs, ocr: &Ocr, episodes: &EpisodeIndex<'a>, matching: &MatchParams, interactive: bool) -> Result<&'a Episode> {
+    let (name, lowest, distance) = get_episode_name(ocr, image, episodes, matching, interactive)?;
+    info!(path, name, "episode name");
+
+    let top = args.top.map(|n| get_closest_episodes(&name, episodes.episodes(), matching, n).into_iter().map(|(episode, distance)| Ranked {
+        name: &episode.name,
+        season_episode: &episode.season_and_episode,
+        distance,
+    }).collect::<Vec<_>>());
+    if let (Some(top), OutputFormat::Text) = (&top, args.format) {
+        println!("{} reads \"{}\":", path, name);
+        for (i, ranked) in top.iter().enumerate() {
+            println!("{:>3}. {} {} (distance {})", i + 1, ranked.season_episode, ranked.name, ranked.distance);
+        }
+    }
+    if !matching.is_confident(distance) {
+        return Err(VideoNamerError::NoConfidentMatch { name: lowest.name.clone(), distance }.into());
+    }
+
+    info!(path, lowest.name, lowest.season_and_episode, distance, "closest episode");
+    warn_if_ambiguous(&name, episodes.episodes(), matching);
+    if args.format == OutputFormat::Json {
+        Identification {
+            path,
+            raw_ocr_text: &name,
+            matched_name: &lowest.name,
+            matched_season_episode: &lowest.season_and_episode,
+            distance,
+            frame_index: None,
+            top,
+        }.print()?;
+    }
+    Ok(lowest)
+}
+
+// a runner-up within --ambiguity-margin of the match usually means the OCR text landed between
+// two similar names
+fn warn_if_ambiguous(name: &str, episodes: &[Episode], matching: &MatchParams) {
+    if let [(best, distance), (runner_up, runner_up_distance)] = get_closest_episodes(name, episodes, matching, 2)[..] {
+        if matching.is_ambiguous(distance, runner_up_distance) {
+            warn!(name, best.name, distance, runner_up.name, runner_up_distance, "ambiguous match");
+        }
     }
 }
 
+// pixels of room left around the text with --crop
+const CROP_PADDING: u32 = 16;
+
+fn episode_name(args: &EpisodeNameArgs, matching: &MatchParams, show: &Show, ocr: &Ocr, cache: Option<&FrameCache>, interactive: bool) -> Result<()> {
+    let EpisodeNameArgs { path, output, extract, crop, at, frames, format } = args;
+    let episodes = get_episode_names(&show.episodes, &show.columns, &show.exclude)?;
+    debug!(len = episodes.len(), "episodes loaded");
+    let index = EpisodeIndex::new(&episodes, matching);
+    // with --at the frame is taken as the title card whatever colour it is
+    let candidates = match at {
+        Some(seconds) => vec![frame_at(Path::new(path), *seconds, extract)?],
+        None => match cache {
+            Some(cache) => cache.extract_frames(Path::new(path), extract)?,
+            None => extract_frames(Path::new(path), extract)?,
+        },
+    };
 
-#[derive(Debug, Deserialize, Clone)]
-struct Episode {
-    name: String,
-    #[serde(rename = "season")]
-    season_and_episode: String,
+    if let Some(Candidate { frame, frame_index: index, name, episode: lowest, distance }) = best_candidate(ocr, candidates, &index, matching, interactive)? {
+        info!(index, "found a blue frame");
+        // write frame to output
+        let region = if *crop { ocr.text_region(&frame, CROP_PADDING)? } else { None };
+        match region {
+            Some((x, y, width, height)) => frames.save(&image::imageops::crop_imm(&frame, x, y, width, height).to_image(), Path::new(output))?,
+            None => frames.save(&frame, Path::new(output))?,
+        }
+
+        info!(name, "episode name");
+        if !matching.is_confident(distance) {
+            return Err(VideoNamerError::NoConfidentMatch { name: lowest.name.clone(), distance }.into());
+        }
+        info!(lowest.name, lowest.season_and_episode, distance, "closest episode");
+        warn_if_ambiguous(&name, &episodes, matching);
+        match format {
+            OutputFormat::Json => Identification {
+                path,
+                raw_ocr_text: &name,
+                matched_name: &lowest.name,
+                matched_season_episode: &lowest.season_and_episode,
+                distance,
+                frame_index: Some(index),
+                top: None,
+            }.print()?,
+            OutputFormat::Text => println!("{}", lowest.name),
+        }
+        Ok(())
+    } else {
+        Err(VideoNamerError::NoBlueFrame(path.clone()).into())
+    }
 }
 
-fn get_episode_names(path: &str) -> Result<Vec<Episode>> {
-    let mut rdr = csv::Reader::from_reader(std::fs::File::open(path)?);
-    rdr.deserialize().collect::<csv::Result<Vec<Episode>>>().map_err(|e| anyhow!(e.to_string()))
-}
\ No newline at end of file
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn renamed_file_keeps_its_extension() {
+        let dir = std::env::temp_dir().join(format!("video_namer-{}-extension", std::process::id()));
+        std::fs::create_dir_all(&dir).unwrap();
+        let fixture = dir.join("episode.mp4");
+        File::create(&fixture).unwrap();
+        let template: Template = TEMPLATE.parse().unwrap();
+        let episode = Episode { name: "Magic Xylophone".to_string(), season_and_episode: "S01E01".to_string(), aka: vec![] };
+        let destination = Destination { output_dir: None, on_conflict: OnConflict::Skip, link: None, write_nfo: false };
+        let new_path = destination.path_for(&fixture, &episode, &template, &Show::default(), None);
+        let journal = dir.join(JOURNAL);
+        let placed = destination.place(&fixture, &new_path, &episode, 0.0, &journal);
+        let renamed = dir.join("Bluey - S01E01 - Magic Xylophone.mp4");
+        let (renamed_exists, fixture_exists, journaled) = (renamed.exists(), fixture.exists(), journal.exists());
+        std::fs::remove_dir_all(&dir).unwrap();
+        placed.unwrap();
+        assert_eq!(new_path, renamed);
+        assert!(renamed_exists);
+        assert!(!fixture_exists);
+        assert!(journaled);
+        // with no extension to keep, it becomes an mkv
+        assert_eq!(file_extension(Path::new("episode")), "mkv");
+        assert_eq!(file_extension(Path::new("episode.")), "mkv");
+    }
+}
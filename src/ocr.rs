@@ -0,0 +1,384 @@
+use std::borrow::Cow;
+use std::path::{Path, PathBuf};
+use std::str::FromStr;
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::time::{Duration, Instant};
+use anyhow::{anyhow, Result};
+use image::imageops::FilterType;
+use image::{Rgb, RgbImage};
+use indicatif::ProgressBar;
+use inquire::Select;
+use ocrs::{ImageSource, OcrEngine};
+use tracing::{debug, warn};
+use crate::{by_distance, get_corrected_episode_name, Episode, EpisodeIndex, MatchParams, VideoNamerError, PROGRESS};
+
+// where the OCR models are, when they aren't in the usual places
+#[derive(clap::Args, Debug, Clone, Default)]
+pub struct ModelPaths {
+    /// text detection model [default: text-detection.rten next to the binary, in
+    /// ~/.local/share/video_namer, or in the source tree]
+    #[clap(long, global = true, env = "VIDEO_NAMER_DETECTION_MODEL")]
+    pub detection_model: Option<PathBuf>,
+
+    /// text recognition model [default: text-recognition.rten, looked for like --detection-model]
+    #[clap(long, global = true, env = "VIDEO_NAMER_RECOGNITION_MODEL")]
+    pub recognition_model: Option<PathBuf>,
+}
+
+// which of the lines OCR reads are worth matching against episode names
+#[derive(clap::Args, Debug, Clone, Copy)]
+pub struct LineFilter {
+    /// ignore detected text lines shorter than this many characters, counting spaces. this is per
+    /// line, not per word, so short words in a longer title are kept
+    #[clap(long, global = true, default_value_t = 2)]
+    pub min_line_len: usize,
+
+    /// keep lines without any letters, like on-screen timers, which are dropped otherwise. title
+    /// cards that show just an episode number need this
+    #[clap(long, global = true)]
+    pub keep_numeric_lines: bool,
+}
+
+impl Default for LineFilter {
+    fn default() -> Self {
+        LineFilter { min_line_len: 2, keep_numeric_lines: false }
+    }
+}
+
+impl LineFilter {
+    /// whether a line could be (part of) a title
+    pub fn keeps(&self, line: &str) -> bool {
+        line.chars().count() >= self.min_line_len && (self.keep_numeric_lines || line.chars().any(char::is_alphabetic))
+    }
+}
+
+// what's done to a frame before it's read. the whole frame is still used to find the title card
+#[derive(clap::Args, Debug, Clone, Copy, Default)]
+pub struct Preprocess {
+    /// shrink frames so neither side is longer than this many pixels before reading them, which
+    /// makes OCR on 4K video much faster. title text is big enough to survive it, and smaller
+    /// frames are left alone
+    #[clap(long, global = true, value_parser = clap::builder::RangedU64ValueParser::<u32>::new().range(1..))]
+    pub ocr_max_dim: Option<u32>,
+
+    /// only read this part of each frame, given as x,y,width,height fractions of the frame's size
+    /// like 0.1,0.2,0.8,0.6, to leave out a channel logo in a corner
+    #[clap(long, global = true)]
+    pub roi: Option<Roi>,
+}
+
+impl Preprocess {
+    /// the frame cropped to --roi and then shrunk to --ocr-max-dim, borrowed when neither changes it
+    pub fn apply<'a>(&self, frame: &'a RgbImage) -> Cow<'a, RgbImage> {
+        let frame = match self.roi {
+            Some(roi) => Cow::Owned(roi.crop(frame)),
+            None => Cow::Borrowed(frame),
+        };
+        match shrink(&frame, self.ocr_max_dim) {
+            Cow::Owned(shrunk) => Cow::Owned(shrunk),
+            Cow::Borrowed(_) => frame,
+        }
+    }
+}
+
+/// a region of a frame, each edge as a fraction (0.0 to 1.0) of the frame's width or height
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Roi {
+    pub x: f64,
+    pub y: f64,
+    pub width: f64,
+    pub height: f64,
+}
+
+impl FromStr for Roi {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> Result<Self> {
+        let parts = s.split(',').map(|part| part.trim().parse::<f64>()).collect::<Result<Vec<_>, _>>()
+            .map_err(|_| anyhow!("expected x,y,width,height as fractions of the frame, got {}", s))?;
+        let [x, y, width, height] = parts[..] else {
+            return Err(anyhow!("expected x,y,width,height as fractions of the frame, got {}", s));
+        };
+        if [x, y].iter().any(|v| !(0.0..1.0).contains(v)) || width <= 0.0 || height <= 0.0 || x + width > 1.0 + f64::EPSILON || y + height > 1.0 + f64::EPSILON {
+            return Err(anyhow!("the region {} isn't inside the frame; each value is a fraction of its size", s));
+        }
+        Ok(Roi { x, y, width, height })
+    }
+}
+
+impl Roi {
+    /// the part of `frame` in the region, at least a pixel each way
+    pub fn crop(&self, frame: &RgbImage) -> RgbImage {
+        let (left, top, width, height) = self.in_pixels(frame.width(), frame.height());
+        image::imageops::crop_imm(frame, left, top, width, height).to_image()
+    }
+
+    /// the region of a frame this size as left, top, width and height in pixels, at least a pixel
+    /// each way
+    pub fn in_pixels(&self, frame_width: u32, frame_height: u32) -> (u32, u32, u32, u32) {
+        let left = ((self.x * frame_width as f64) as u32).min(frame_width.saturating_sub(1));
+        let top = ((self.y * frame_height as f64) as u32).min(frame_height.saturating_sub(1));
+        let width = ((self.width * frame_width as f64).round() as u32).clamp(1, frame_width - left);
+        let height = ((self.height * frame_height as f64).round() as u32).clamp(1, frame_height - top);
+        (left, top, width, height)
+    }
+}
+
+// the first of the places a model could be installed that has it: next to the binary, in the
+// user's data directory, or in the source tree for `cargo run`
+fn find_model(kind: &'static str, name: &str) -> Result<PathBuf> {
+    let mut dirs = vec![];
+    if let Some(dir) = std::env::current_exe().ok().and_then(|exe| exe.parent().map(|dir| dir.to_path_buf())) {
+        dirs.push(dir);
+    }
+    if let Some(data) = std::env::var_os("XDG_DATA_HOME").map(PathBuf::from).or_else(|| std::env::var_os("HOME").map(|home| PathBuf::from(home).join(".local/share"))) {
+        dirs.push(data.join("video_namer"));
+    }
+    dirs.push(PathBuf::from(env!("CARGO_MANIFEST_DIR")));
+
+    let paths = dirs.into_iter().map(|dir| dir.join(name)).collect::<Vec<_>>();
+    paths.iter().find(|path| path.exists()).cloned().ok_or_else(|| {
+        let searched = paths.iter().map(|path| path.display().to_string()).collect::<Vec<_>>();
+        VideoNamerError::ModelLoad { kind, path: PathBuf::from(name), message: format!("it isn't in any of {}", searched.join(", ")) }.into()
+    })
+}
+
+// load a model from where it was said to be, or wherever `name` is installed
+fn load_model(kind: &'static str, path: Option<&Path>, name: &str) -> Result<rten::Model> {
+    let path = match path {
+        Some(path) => path.to_path_buf(),
+        None => find_model(kind, name)?,
+    };
+    if !path.is_file() {
+        return Err(VideoNamerError::ModelLoad { kind, path, message: "no such file".to_string() }.into());
+    }
+    rten::Model::load_file(&path).map_err(|e| VideoNamerError::ModelLoad { kind, path, message: e.to_string() }.into())
+}
+
+// a spinner that keeps turning while OCR is busy, so slow steps don't look like a hang
+fn spinner(message: &'static str) -> ProgressBar {
+    let spinner = PROGRESS.add(ProgressBar::new_spinner().with_message(message));
+    spinner.enable_steady_tick(Duration::from_millis(100));
+    spinner
+}
+
+/// the text detection and recognition models, loaded once and shared by everything that reads
+/// title cards
+pub struct Ocr {
+    engine: OcrEngine,
+    lines: LineFilter,
+    preprocess: Preprocess,
+    // where to save each frame read with what was detected in it drawn on, for --debug-ocr
+    debug_dir: Option<PathBuf>,
+    // how many frames have been saved there, to name the next one
+    debug_saved: AtomicUsize,
+}
+
+// the colours detections are drawn in
+const WORD_COLOR: Rgb<u8> = Rgb([0, 255, 0]);
+const LINE_COLOR: Rgb<u8> = Rgb([255, 0, 0]);
+
+impl Ocr {
+    /// load the models. this is slow, so do it once up front and pass the result around. with a
+    /// `debug_dir`, every frame read is saved there with the words found in it outlined
+    pub fn load(models: &ModelPaths, lines: LineFilter, preprocess: Preprocess, debug_dir: Option<&Path>) -> Result<Self> {
+        if let Some(dir) = debug_dir {
+            std::fs::create_dir_all(dir).map_err(|e| anyhow!("couldn't create OCR debug directory {}: {}", dir.display(), e))?;
+        }
+        let spinner = spinner("loading models");
+        let detection_model = load_model("detection", models.detection_model.as_deref(), "text-detection.rten")?;
+        let recognition_model = load_model("recognition", models.recognition_model.as_deref(), "text-recognition.rten")?;
+
+        let engine = OcrEngine::new(ocrs::OcrEngineParams {
+            detection_model: Some(detection_model),
+            recognition_model: Some(recognition_model),
+            ..Default::default()
+        })?;
+        spinner.finish_and_clear();
+        Ok(Ocr { engine, lines, preprocess, debug_dir: debug_dir.map(Path::to_path_buf), debug_saved: AtomicUsize::new(0) })
+    }
+
+    /// the lines of text in a frame, or the part of it --roi picks, leaving out the ones the line
+    /// filter drops, like single characters which are usually noise
+    pub fn recognize(&self, frame: &RgbImage) -> Result<Vec<String>> {
+        let engine = &self.engine;
+        let spinner = spinner("preparing image");
+        let frame = self.preprocess.apply(frame);
+        let img_source = ImageSource::from_bytes(frame.as_raw(), frame.dimensions())?;
+        let ocr_input = engine.prepare_input(img_source)?;
+
+        spinner.set_message("detecting words");
+        let word_rects = engine.detect_words(&ocr_input)?;
+        debug!(len = word_rects.len(), "detected words");
+        spinner.set_message("finding lines");
+        let line_rects = engine.find_text_lines(&ocr_input, &word_rects);
+        debug!(len = line_rects.len(), "detected lines");
+        spinner.set_message("recognizing text");
+        let line_texts = engine.recognize_text(&ocr_input, &line_rects)?;
+        spinner.finish_and_clear();
+
+        if let Some(dir) = &self.debug_dir {
+            let words = word_rects.iter().map(|word| word.corners().map(|p| (p.x, p.y))).collect::<Vec<_>>();
+            let lines = line_rects.iter().map(|line| line.iter().flat_map(|word| word.corners()).map(|p| (p.x, p.y)).collect::<Vec<_>>()).collect::<Vec<_>>();
+            let path = dir.join(format!("ocr-{:04}.png", self.debug_saved.fetch_add(1, Ordering::Relaxed)));
+            let texts = line_texts.iter().map(|text| text.as_ref().map(|text| text.to_string()).unwrap_or_default()).collect::<Vec<_>>();
+            match draw_detections(&frame, &words, &lines).save(&path) {
+                Ok(()) => debug!(path = %path.display(), ?texts, "saved OCR detections"),
+                Err(e) => warn!("couldn't save OCR detections to {}: {}", path.display(), e),
+            }
+        }
+
+        // everything that was read, including what's thrown away, to see when subtitles or a
+        // watermark were picked up instead of the title
+        for (words, text) in line_rects.iter().zip(&line_texts) {
+            let text = text.as_ref().map(|text| text.to_string()).unwrap_or_default();
+            let corners = words.iter().flat_map(|word| word.corners()).map(|p| (p.x, p.y)).collect::<Vec<_>>();
+            let (left, top, right, bottom) = bounds(&corners).unwrap_or_default();
+            debug!(text, kept = self.lines.keeps(&text), left, top, right, bottom, "read line");
+        }
+
+        let lines = line_texts.iter().flatten().map(|x| x.to_string()).filter(|x| self.lines.keeps(x)).collect::<Vec<_>>();
+        debug!("{:#?}", lines);
+        Ok(lines)
+    }
+
+    /// the part of a frame with text in it, as x, y, width and height, grown by `padding` pixels
+    /// on each side without going past the edges. None when there's no text
+    pub fn text_region(&self, frame: &RgbImage, padding: u32) -> Result<Option<(u32, u32, u32, u32)>> {
+        let img_source = ImageSource::from_bytes(frame.as_raw(), frame.dimensions())?;
+        let ocr_input = self.engine.prepare_input(img_source)?;
+        let word_rects = self.engine.detect_words(&ocr_input)?;
+        let line_rects = self.engine.find_text_lines(&ocr_input, &word_rects);
+
+        let corners = line_rects.iter().flatten().flat_map(|rect| rect.corners()).map(|p| (p.x, p.y)).collect::<Vec<_>>();
+        let Some((left, top, right, bottom)) = bounds(&corners) else {
+            return Ok(None);
+        };
+        let (width, height) = frame.dimensions();
+        let clamp = |value: f32, max: u32| (value.max(0.0) as u32).min(max);
+        let left = clamp(left, width).saturating_sub(padding);
+        let top = clamp(top, height).saturating_sub(padding);
+        let right = (clamp(right.ceil(), width) + padding).min(width);
+        let bottom = (clamp(bottom.ceil(), height) + padding).min(height);
+        if right <= left || bottom <= top {
+            return Ok(None);
+        }
+        Ok(Some((left, top, right - left, bottom - top)))
+    }
+}
+
+// the frame scaled down, keeping its aspect ratio, so neither side is longer than `max_dim`.
+// frames that already fit are left alone
+fn shrink(frame: &RgbImage, max_dim: Option<u32>) -> Cow<'_, RgbImage> {
+    let (width, height) = frame.dimensions();
+    let Some(max_dim) = max_dim.filter(|max_dim| width.max(height) > *max_dim) else {
+        return Cow::Borrowed(frame);
+    };
+    let scale = max_dim as f64 / width.max(height) as f64;
+    let (new_width, new_height) = (((width as f64 * scale).round() as u32).max(1), ((height as f64 * scale).round() as u32).max(1));
+    debug!(width, height, new_width, new_height, "shrinking frame for OCR");
+    Cow::Owned(image::imageops::resize(frame, new_width, new_height, FilterType::Triangle))
+}
+
+// a copy of the frame with each detected word's corners joined up in WORD_COLOR and a box in
+// LINE_COLOR around each line they were grouped into
+fn draw_detections(frame: &RgbImage, words: &[[(f32, f32); 4]], lines: &[Vec<(f32, f32)>]) -> RgbImage {
+    let mut frame = frame.clone();
+    for corners in words {
+        for i in 0..corners.len() {
+            draw_line(&mut frame, corners[i], corners[(i + 1) % corners.len()], WORD_COLOR);
+        }
+    }
+    for (left, top, right, bottom) in lines.iter().filter_map(|corners| bounds(corners)) {
+        let box_corners = [(left, top), (right, top), (right, bottom), (left, bottom)];
+        for i in 0..box_corners.len() {
+            draw_line(&mut frame, box_corners[i], box_corners[(i + 1) % box_corners.len()], LINE_COLOR);
+        }
+    }
+    frame
+}
+
+// a one pixel line between two points, leaving out whatever is past the frame's edges
+fn draw_line(frame: &mut RgbImage, from: (f32, f32), to: (f32, f32), color: Rgb<u8>) {
+    let steps = (to.0 - from.0).abs().max((to.1 - from.1).abs()).ceil().max(1.0) as usize;
+    for step in 0..=steps {
+        let t = step as f32 / steps as f32;
+        let (x, y) = ((from.0 + (to.0 - from.0) * t).round(), (from.1 + (to.1 - from.1) * t).round());
+        if x >= 0.0 && y >= 0.0 && (x as u32) < frame.width() && (y as u32) < frame.height() {
+            frame.put_pixel(x as u32, y as u32, color);
+        }
+    }
+}
+
+// the left, top, right and bottom edges of the box around some points, None when there aren't any
+fn bounds(points: &[(f32, f32)]) -> Option<(f32, f32, f32, f32)> {
+    if points.is_empty() {
+        return None;
+    }
+    let left = points.iter().map(|p| p.0).fold(f32::MAX, f32::min);
+    let top = points.iter().map(|p| p.1).fold(f32::MAX, f32::min);
+    let right = points.iter().map(|p| p.0).fold(f32::MIN, f32::max);
+    let bottom = points.iter().map(|p| p.1).fold(f32::MIN, f32::max);
+    Some((left, top, right, bottom))
+}
+
+/// read a title card and find the episode it names, along with the text that named it and how far
+/// that text is from the episode's name. when there's more than one line of text, each line and
+/// each run of adjacent lines joined together is tried, and the closest to an episode is used, or
+/// when `interactive` they're offered closest first to choose from
+pub fn get_episode_name<'a>(ocr: &Ocr, frame: &RgbImage, episodes: &EpisodeIndex<'a>, matching: &MatchParams, interactive: bool) -> Result<(String, &'a Episode, f64)> {
+    let start = Instant::now();
+    let lines = ocr.recognize(frame)?;
+    let ocr_time = start.elapsed();
+    if lines.is_empty() {
+        return Err(VideoNamerError::NoTextDetected.into());
+    }
+    let mut options = join_lines(&lines).into_iter()
+        .filter_map(|text| get_corrected_episode_name(&text, episodes, matching).map(|(episode, distance)| (text, episode, distance)))
+        .collect::<Vec<_>>();
+    options.sort_by(|(_, a_episode, a), (_, b_episode, b)| by_distance(&(a_episode, *a), &(b_episode, *b)));
+    debug!(ocr = ?ocr_time, matching = ?start.elapsed() - ocr_time, "read a title card");
+    if options.is_empty() {
+        return Err(VideoNamerError::NoEpisodeFound.into());
+    }
+
+    if interactive && lines.len() > 1 {
+        let labels = options.iter().map(|(text, episode, distance)| format!("{} ({}, distance {})", text, episode.name, distance)).collect();
+        let choice = Select::new("Choose an OCR option:", labels).raw_prompt()?;
+        return Ok(options.swap_remove(choice.index));
+    }
+    Ok(options.swap_remove(0))
+}
+
+/// every line on its own, then every run of two or more adjacent lines joined with spaces, for
+/// titles that are split over several lines like "The Pool" / "Party"
+pub fn join_lines(lines: &[String]) -> Vec<String> {
+    let mut options = lines.to_vec();
+    for start in 0..lines.len() {
+        for end in start + 2..=lines.len() {
+            options.push(lines[start..end].join(" "));
+        }
+    }
+    options
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    #[ignore = "needs text-detection.rten and text-recognition.rten from download-models.sh"]
+    fn loads_the_models() {
+        let dir = Path::new(env!("CARGO_MANIFEST_DIR"));
+        let models = ModelPaths { detection_model: Some(dir.join("text-detection.rten")), recognition_model: Some(dir.join("text-recognition.rten")) };
+        let ocr = Ocr::load(&models, LineFilter::default(), Preprocess::default(), None).unwrap();
+        assert!(ocr.recognize(&RgbImage::new(64, 64)).unwrap().is_empty());
+    }
+
+    #[test]
+    fn missing_model_is_a_model_load_error() {
+        let models = ModelPaths { detection_model: Some(PathBuf::from("no/such/text-detection.rten")), ..ModelPaths::default() };
+        let e = Ocr::load(&models, LineFilter::default(), Preprocess::default(), None).err().unwrap();
+        assert!(matches!(e.downcast_ref(), Some(VideoNamerError::ModelLoad { kind: "detection", .. })), "{}", e);
+    }
+}
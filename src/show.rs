@@ -0,0 +1,58 @@
+use std::path::PathBuf;
+use anyhow::{anyhow, Result};
+use regex::Regex;
+use serde::Deserialize;
+use crate::{deserialize_exclude, ColorThresholds, CsvColumns};
+
+/// a show profile: what the show is called, where its episodes live, which of their columns to
+/// read and which episodes to leave out, and how to spot its title cards
+#[derive(Debug, Deserialize)]
+pub struct Show {
+    pub name: String,
+    pub episodes: String,
+    #[serde(default)]
+    pub columns: CsvColumns,
+    #[serde(default, deserialize_with = "deserialize_exclude")]
+    pub exclude: Vec<Regex>,
+    #[serde(default)]
+    pub thresholds: ColorThresholds,
+}
+
+impl Default for Show {
+    fn default() -> Self {
+        Show {
+            name: "Bluey".to_string(),
+            episodes: "bluey.csv".to_string(),
+            columns: CsvColumns::default(),
+            exclude: vec![],
+            thresholds: ColorThresholds::default(),
+        }
+    }
+}
+
+/// load <name>.toml from $XDG_CONFIG_HOME/video_namer/shows, or shows/ when it isn't there. the
+/// episodes path in a profile is relative to the profile rather than to where it's run from
+pub fn load_show(name: &str) -> Result<Show> {
+    let paths = show_dirs().into_iter().map(|dir| dir.join(format!("{}.toml", name))).collect::<Vec<_>>();
+    let path = paths.iter().find(|path| path.is_file()).ok_or_else(|| {
+        let searched = paths.iter().map(|path| path.display().to_string()).collect::<Vec<_>>();
+        anyhow!("show profile {} not found, looked for {}", name, searched.join(" and "))
+    })?;
+    let contents = std::fs::read_to_string(path).map_err(|e| anyhow!("couldn't read show profile {}: {}", path.display(), e))?;
+    let mut show: Show = toml::from_str(&contents).map_err(|e| anyhow!("invalid show profile {}: {}", path.display(), e))?;
+    if let Some(dir) = path.parent() {
+        show.episodes = dir.join(&show.episodes).to_string_lossy().into_owned();
+    }
+    Ok(show)
+}
+
+// where profiles are looked for, in order: the user's config directory, then shows/ in the
+// current directory
+fn show_dirs() -> Vec<PathBuf> {
+    let mut dirs = vec![];
+    if let Some(config) = std::env::var_os("XDG_CONFIG_HOME").map(PathBuf::from).or_else(|| std::env::var_os("HOME").map(|home| PathBuf::from(home).join(".config"))) {
+        dirs.push(config.join("video_namer").join("shows"));
+    }
+    dirs.push(PathBuf::from("shows"));
+    dirs
+}
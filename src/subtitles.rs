@@ -0,0 +1,92 @@
+use std::path::Path;
+use anyhow::Result;
+use ffmpeg_next::codec;
+use ffmpeg_next::media::Type;
+use tracing::{debug, warn};
+
+// captions from further into a video than this are too late to name the episode
+const CAPTION_WINDOW_SECONDS: f64 = 300.0;
+// and there's no point reading more of them than this
+const MAX_CAPTIONS: usize = 20;
+
+/// text a video carries besides its pictures that might name the episode: the title in its
+/// metadata, its chapter titles, and the first few captions of its text subtitles. picture
+/// subtitles, like DVD and Blu-ray ones, are left out, and so are captions that can't be decoded
+pub fn embedded_text(filename: &Path) -> Result<Vec<String>> {
+    let mut ictx = ffmpeg_next::format::input(filename)?;
+    let mut texts = vec![];
+    if let Some(title) = ictx.metadata().get("title").map(clean_caption) {
+        texts.push(title);
+    }
+    texts.extend(ictx.chapters().filter_map(|chapter| chapter.metadata().get("title").map(clean_caption)));
+
+    // the title and chapters are still worth having when the subtitles can't be read
+    match captions(&mut ictx) {
+        Ok(captions) => texts.extend(captions),
+        Err(e) => warn!("couldn't read the subtitles of {}: {}", filename.display(), e),
+    }
+    texts.retain(|text| !text.is_empty());
+    debug!(len = texts.len(), "read embedded text from {}", filename.display());
+    Ok(texts)
+}
+
+// the first MAX_CAPTIONS captions of the best subtitle stream, if it's a text one, stopping at
+// CAPTION_WINDOW_SECONDS
+fn captions(ictx: &mut ffmpeg_next::format::context::Input) -> Result<Vec<String>> {
+    let Some(stream) = ictx.streams().best(Type::Subtitle) else {
+        return Ok(vec![]);
+    };
+    let index = stream.index();
+    let time_base = f64::from(stream.time_base());
+    let mut decoder = codec::context::Context::from_parameters(stream.parameters())?.decoder().subtitle()?;
+
+    let mut captions = vec![];
+    for (stream, packet) in ictx.packets() {
+        if stream.index() != index {
+            continue;
+        }
+        if captions.len() >= MAX_CAPTIONS || packet.pts().is_some_and(|pts| pts as f64 * time_base > CAPTION_WINDOW_SECONDS) {
+            break;
+        }
+        let mut subtitle = codec::subtitle::Subtitle::new();
+        match decoder.decode(&packet, &mut subtitle) {
+            Ok(true) => {}
+            Ok(false) => continue,
+            // one bad packet shouldn't lose the captions around it
+            Err(e) => {
+                debug!("skipping a subtitle packet that won't decode: {}", e);
+                continue;
+            }
+        }
+        for rect in subtitle.rects() {
+            match rect {
+                codec::subtitle::Rect::Text(text) => captions.push(clean_caption(text.get())),
+                codec::subtitle::Rect::Ass(ass) => captions.push(clean_caption(ass_text(ass.get()))),
+                _ => {}
+            }
+        }
+    }
+    Ok(captions)
+}
+
+// the text of an ASS dialogue event, which comes after eight comma separated fields like the
+// style and margins
+fn ass_text(event: &str) -> &str {
+    event.splitn(9, ',').nth(8).unwrap_or(event)
+}
+
+// a caption without markup: {\i1} style ASS overrides, <i> style tags, and ASS line breaks
+fn clean_caption(caption: &str) -> String {
+    let mut text = String::new();
+    let mut closing = None;
+    for c in caption.replace("\\N", " ").replace("\\n", " ").chars() {
+        match (closing, c) {
+            (None, '{') => closing = Some('}'),
+            (None, '<') => closing = Some('>'),
+            (Some(end), c) if c == end => closing = None,
+            (Some(_), _) => {}
+            (None, c) => text.push(c),
+        }
+    }
+    text.split_whitespace().collect::<Vec<_>>().join(" ")
+}
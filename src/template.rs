@@ -0,0 +1,108 @@
+use std::str::FromStr;
+use anyhow::{anyhow, Result};
+use regex::Regex;
+use crate::{Episode, Show};
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+enum Placeholder {
+    Show,
+    SeasonEpisode,
+    Name,
+    Ext,
+    Frame,
+}
+
+const PLACEHOLDERS: &[(&str, Placeholder)] = &[
+    ("show", Placeholder::Show),
+    ("season_episode", Placeholder::SeasonEpisode),
+    ("name", Placeholder::Name),
+    ("ext", Placeholder::Ext),
+    ("frame", Placeholder::Frame),
+];
+
+#[derive(Debug, Clone)]
+enum TemplatePart {
+    Literal(String),
+    Placeholder(Placeholder),
+}
+
+/// an output filename template like "{show} - {season_episode} - {name}.{ext}"
+#[derive(Debug, Clone)]
+pub struct Template {
+    parts: Vec<TemplatePart>,
+    // matches filenames this template renders, with a capture group per placeholder
+    pattern: Regex,
+}
+
+impl FromStr for Template {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> Result<Self> {
+        let mut parts = vec![];
+        let mut rest = s;
+        while let Some(start) = rest.find('{') {
+            if start > 0 {
+                parts.push(TemplatePart::Literal(rest[..start].to_string()));
+            }
+            let end = start + rest[start..].find('}').ok_or(anyhow!("unclosed '{{' in template"))?;
+            let name = &rest[start + 1..end];
+            let placeholder = PLACEHOLDERS.iter().find(|(n, _)| *n == name).map(|(_, p)| *p).ok_or_else(|| {
+                let valid = PLACEHOLDERS.iter().map(|(n, _)| format!("{{{}}}", n)).collect::<Vec<_>>();
+                anyhow!("unknown placeholder {{{}}} in template, valid placeholders are: {}", name, valid.join(", "))
+            })?;
+            parts.push(TemplatePart::Placeholder(placeholder));
+            rest = &rest[end + 1..];
+        }
+        if !rest.is_empty() {
+            parts.push(TemplatePart::Literal(rest.to_string()));
+        }
+
+        let pattern = parts.iter().map(|part| match part {
+            TemplatePart::Literal(text) => regex::escape(text),
+            TemplatePart::Placeholder(_) => "(.+?)".to_string(),
+        }).collect::<String>();
+        let pattern = Regex::new(&format!("^{}$", pattern))?;
+        Ok(Template { parts, pattern })
+    }
+}
+
+impl Template {
+    /// the filename for an episode. {frame} is the index of the frame the title card was found
+    /// at, and is left empty when that isn't known
+    pub fn render(&self, show: &Show, episode: &Episode, ext: &str, frame: Option<usize>) -> String {
+        let frame = frame.map(|frame| frame.to_string()).unwrap_or_default();
+        self.parts.iter().map(|part| match part {
+            TemplatePart::Literal(text) => text.as_str(),
+            TemplatePart::Placeholder(Placeholder::Show) => &show.name,
+            TemplatePart::Placeholder(Placeholder::SeasonEpisode) => &episode.season_and_episode,
+            TemplatePart::Placeholder(Placeholder::Name) => &episode.name,
+            TemplatePart::Placeholder(Placeholder::Ext) => ext,
+            TemplatePart::Placeholder(Placeholder::Frame) => &frame,
+        }).collect()
+    }
+
+    // the value of each placeholder in a filename this template could have rendered
+    fn parse<'a>(&self, filename: &'a str) -> Option<Vec<(Placeholder, &'a str)>> {
+        let captures = self.pattern.captures(filename)?;
+        let placeholders = self.parts.iter().filter_map(|part| match part {
+            TemplatePart::Placeholder(placeholder) => Some(*placeholder),
+            TemplatePart::Literal(_) => None,
+        });
+        Some(placeholders.zip(captures.iter().skip(1).flatten().map(|m| m.as_str())).collect())
+    }
+
+    /// the episode a filename already names, when it's exactly what this template would have
+    /// rendered for that episode
+    pub fn already_named<'a>(&self, filename: &str, show: &Show, episodes: &'a [Episode]) -> Option<&'a Episode> {
+        let values = self.parse(filename)?;
+        if !values.iter().any(|(placeholder, _)| matches!(placeholder, Placeholder::Name | Placeholder::SeasonEpisode)) {
+            return None;
+        }
+        episodes.iter().find(|episode| values.iter().all(|(placeholder, value)| match placeholder {
+            Placeholder::Show => *value == show.name,
+            Placeholder::SeasonEpisode => *value == episode.season_and_episode,
+            Placeholder::Name => *value == episode.name,
+            Placeholder::Ext | Placeholder::Frame => true,
+        }))
+    }
+}